@@ -0,0 +1,164 @@
+//! Audio codec choice and low-bitrate channel/sample-rate reduction.
+
+use std::fmt;
+
+use crate::command::Options;
+use crate::container::Container;
+use crate::probe::MediaInfo;
+
+/// Audio codec for the output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AudioCodec {
+    /// Pick per container and bitrate; see [`AudioCodec::resolve`].
+    #[default]
+    Auto,
+    /// AAC-LC via ffmpeg's native encoder.
+    Aac,
+    /// HE-AAC (AAC with SBR) via libfdk_aac, much better below 64 kbps.
+    HeAac,
+    /// Opus via libopus, the best codec at low bitrates.
+    Opus,
+}
+
+/// HE-AAC and Opus only beat AAC-LC clearly below this bitrate (bps).
+const LOW_BITRATE: u64 = 64_000;
+
+impl AudioCodec {
+    /// ffmpeg encoder name; `None` for [`AudioCodec::Auto`].
+    pub fn encoder(self) -> Option<&'static str> {
+        match self {
+            AudioCodec::Auto => None,
+            AudioCodec::Aac => Some("aac"),
+            AudioCodec::HeAac => Some("libfdk_aac"),
+            AudioCodec::Opus => Some("libopus"),
+        }
+    }
+
+    /// Whether `container` can carry this codec.
+    pub fn fits(self, container: Container) -> bool {
+        match self {
+            AudioCodec::Auto => true,
+            AudioCodec::Aac | AudioCodec::HeAac => container != Container::WebM,
+            AudioCodec::Opus => container != Container::Mov,
+        }
+    }
+
+    /// Turn [`AudioCodec::Auto`] into a concrete codec. WebM and MKV get
+    /// Opus; MP4 and MOV stay with AAC for player compatibility, using
+    /// HE-AAC below 64 kbps when `has_encoder("libfdk_aac")` says it is
+    /// available. Other codecs are returned unchanged.
+    pub fn resolve(
+        self,
+        container: Container,
+        bitrate: u64,
+        has_encoder: impl Fn(&str) -> bool,
+    ) -> AudioCodec {
+        if self != AudioCodec::Auto {
+            return self;
+        }
+        match container {
+            Container::WebM | Container::Mkv => AudioCodec::Opus,
+            Container::Mp4 | Container::Mov => {
+                if bitrate < LOW_BITRATE && has_encoder("libfdk_aac") {
+                    AudioCodec::HeAac
+                } else {
+                    AudioCodec::Aac
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for AudioCodec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            AudioCodec::Auto => "auto",
+            AudioCodec::Aac => "AAC",
+            AudioCodec::HeAac => "HE-AAC",
+            AudioCodec::Opus => "Opus",
+        })
+    }
+}
+
+/// Concrete audio encoding settings for one output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AudioSettings {
+    /// Never [`AudioCodec::Auto`].
+    pub codec: AudioCodec,
+    /// Bitrate (bps).
+    pub bitrate: u64,
+    /// Downmix to this many channels, if set.
+    pub channels: Option<u32>,
+    /// Resample to this rate (Hz), if set.
+    pub sample_rate: Option<u32>,
+}
+
+impl AudioSettings {
+    /// Settings for `codec` at `bitrate`, given the source's main
+    /// audio stream (see [`MediaInfo::main_audio`]). Small budgets are
+    /// spent on fewer channels and, for AAC and HE-AAC, a lower sample
+    /// rate, rather than smearing them over stereo 48 kHz.
+    pub fn for_bitrate(codec: AudioCodec, bitrate: u64, media: &MediaInfo) -> Self {
+        let source = media.main_audio();
+        let source_channels = source.and_then(|s| s.channels).unwrap_or(2);
+        let source_rate = source.and_then(|s| s.sample_rate).unwrap_or(48_000);
+
+        // Below these rates stereo costs more than it is worth.
+        let mono_below = match codec {
+            AudioCodec::Opus | AudioCodec::HeAac => 32_000,
+            _ => 48_000,
+        };
+        let channels = if source_channels > 1 && bitrate < mono_below {
+            Some(1)
+        } else if source_channels > 2 {
+            // Surround never fits a shrink budget.
+            Some(2)
+        } else {
+            None
+        };
+
+        // Opus always codes at 48 kHz internally, so only AAC is resampled.
+        let sample_rate = match codec {
+            AudioCodec::Aac if bitrate <= 24_000 => Some(22_050),
+            AudioCodec::Aac | AudioCodec::HeAac if bitrate <= 40_000 => Some(32_000),
+            _ => None,
+        }
+        .filter(|rate| *rate < source_rate);
+
+        AudioSettings {
+            codec,
+            bitrate,
+            channels,
+            sample_rate,
+        }
+    }
+
+    /// Encoded packets per second, given the source stream's sample rate.
+    pub(crate) fn packet_rate(&self, source_rate: Option<u32>) -> f64 {
+        // Opus packets are 20 ms; AAC frames are 1024 samples, doubled by SBR.
+        let frame_samples = match self.codec {
+            AudioCodec::Opus => return 50.0,
+            AudioCodec::HeAac => 2048.0,
+            _ => 1024.0,
+        };
+        let rate = self.sample_rate.or(source_rate).unwrap_or(48_000);
+        rate as f64 / frame_samples
+    }
+
+    /// Encoder and options for the audio stream.
+    pub(crate) fn options(&self) -> Options {
+        let encoder = self.codec.encoder().unwrap_or("aac");
+        let mut options: Options = vec![("c", encoder.into())];
+        if self.codec == AudioCodec::HeAac {
+            options.push(("profile", "aac_he".into()));
+        }
+        options.push(("b", format!("{}k", self.bitrate / 1000).into()));
+        if let Some(channels) = self.channels {
+            options.push(("ac", channels.to_string().into()));
+        }
+        if let Some(rate) = self.sample_rate {
+            options.push(("ar", rate.to_string().into()));
+        }
+        options
+    }
+}
@@ -0,0 +1,88 @@
+//! What probes inputs and runs encodes: the ffmpeg and ffprobe
+//! executables by default.
+
+use std::fmt;
+use std::path::Path;
+
+use crate::error::Result;
+use crate::ffmpeg::{self, EncodePlan};
+use crate::probe::{self, MediaInfo};
+use crate::quality::QualityScores;
+use crate::sample::Sample;
+use crate::streams::StreamSelection;
+use crate::ShrinkJob;
+
+/// Reads what an input contains.
+pub trait Prober: fmt::Debug + Send + Sync {
+    fn probe(&self, path: &Path) -> Result<MediaInfo>;
+}
+
+/// Writes a job's output and measures what it cost in quality.
+pub trait Encoder: fmt::Debug + Send + Sync {
+    /// Names of the available encoders, as listed by `ffmpeg -encoders`.
+    fn encoders(&self) -> Result<Vec<String>>;
+
+    /// Encode once as `plan` describes, replacing the job's output.
+    fn encode(&self, plan: &EncodePlan) -> Result<()>;
+
+    /// Rewrap the kept `streams` into the job's output without
+    /// re-encoding them.
+    fn remux(&self, job: &ShrinkJob, media: &MediaInfo, streams: &StreamSelection) -> Result<()>;
+
+    /// Score the job's output, probed as `output`, against its input.
+    fn measure_quality(
+        &self,
+        job: &ShrinkJob,
+        input: &MediaInfo,
+        output: &MediaInfo,
+    ) -> Result<QualityScores>;
+
+    /// Encode `sample` of the input as `plan` describes and score it
+    /// against the same stretch of the input; VMAF is required.
+    fn sample_quality(&self, plan: &EncodePlan, sample: Sample) -> Result<QualityScores>;
+
+    /// Encode `sample` of the input as `plan` describes, without audio,
+    /// and return its size in bytes.
+    fn sample_size(&self, plan: &EncodePlan, sample: Sample) -> Result<u64>;
+}
+
+/// The default backend: `ffmpeg` and `ffprobe` looked up on `PATH`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Ffmpeg;
+
+impl Prober for Ffmpeg {
+    fn probe(&self, path: &Path) -> Result<MediaInfo> {
+        probe::probe(path)
+    }
+}
+
+impl Encoder for Ffmpeg {
+    fn encoders(&self) -> Result<Vec<String>> {
+        ffmpeg::encoders()
+    }
+
+    fn encode(&self, plan: &EncodePlan) -> Result<()> {
+        ffmpeg::encode(plan)
+    }
+
+    fn remux(&self, job: &ShrinkJob, media: &MediaInfo, streams: &StreamSelection) -> Result<()> {
+        ffmpeg::remux(job, media, streams)
+    }
+
+    fn measure_quality(
+        &self,
+        job: &ShrinkJob,
+        input: &MediaInfo,
+        output: &MediaInfo,
+    ) -> Result<QualityScores> {
+        ffmpeg::measure_quality(job, input, output)
+    }
+
+    fn sample_quality(&self, plan: &EncodePlan, sample: Sample) -> Result<QualityScores> {
+        ffmpeg::sample_quality(plan, sample)
+    }
+
+    fn sample_size(&self, plan: &EncodePlan, sample: Sample) -> Result<u64> {
+        ffmpeg::sample_size(plan, sample)
+    }
+}
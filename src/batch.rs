@@ -0,0 +1,271 @@
+//! Shrinking every video in a directory tree.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+
+use crate::container::Container;
+use crate::error::{Error, Result};
+use crate::progress::megabytes;
+use crate::{ShrinkJob, ShrinkOptions, ShrinkReport};
+
+/// File patterns used when no `include` pattern is given.
+pub const DEFAULT_INCLUDE: &[&str] = &["*.mp4", "*.m4v", "*.mov", "*.mkv", "*.webm", "*.avi"];
+
+/// Which files of an input directory become jobs.
+#[derive(Debug, Clone, Default)]
+pub struct BatchOptions {
+    /// Descend into subdirectories.
+    pub recursive: bool,
+    /// Globs a file's path relative to the input directory must match;
+    /// [`DEFAULT_INCLUDE`] if empty. Matching ignores case.
+    pub include: Vec<String>,
+    /// Globs that drop a file even if it matches `include`.
+    pub exclude: Vec<String>,
+}
+
+impl BatchOptions {
+    pub fn recursive(mut self, enabled: bool) -> Self {
+        self.recursive = enabled;
+        self
+    }
+
+    pub fn include(mut self, patterns: Vec<String>) -> Self {
+        self.include = patterns;
+        self
+    }
+
+    pub fn exclude(mut self, patterns: Vec<String>) -> Self {
+        self.exclude = patterns;
+        self
+    }
+}
+
+/// One job per matching file under `input_dir`, writing to the same
+/// relative path under `output_dir` with the output container's
+/// extension. Where that would send two inputs to one output, such as
+/// `clip.avi` and `clip.mp4`, inputs of another extension keep theirs
+/// (`clip.avi.mp4`). Jobs are sorted by input path.
+pub fn collect_jobs(
+    input_dir: &Path,
+    output_dir: &Path,
+    batch: &BatchOptions,
+    options: &ShrinkOptions,
+) -> Result<Vec<ShrinkJob>> {
+    let include = if batch.include.is_empty() {
+        glob_set(DEFAULT_INCLUDE)?
+    } else {
+        glob_set(&batch.include)?
+    };
+    let exclude = glob_set(&batch.exclude)?;
+    // Never pick up our own outputs when writing inside the input tree.
+    let skip_dir = fs::canonicalize(output_dir).ok();
+
+    let mut files = Vec::new();
+    walk(input_dir, batch.recursive, skip_dir.as_deref(), &mut files).map_err(|source| {
+        Error::Input {
+            path: input_dir.to_path_buf(),
+            source,
+        }
+    })?;
+    files.sort();
+
+    let matched: Vec<(PathBuf, PathBuf)> = files
+        .into_iter()
+        .filter_map(|path| {
+            let rel = path.strip_prefix(input_dir).ok()?.to_path_buf();
+            if !include.is_match(&rel) || exclude.is_match(&rel) {
+                return None;
+            }
+            Some((path, rel))
+        })
+        .collect();
+    let extension = |rel: &Path| {
+        options
+            .container
+            .or_else(|| Container::from_path(rel))
+            .unwrap_or_default()
+            .extension()
+    };
+    // Compare without case, as the default filesystems on macOS and
+    // Windows do.
+    let key = |path: &Path| path.to_string_lossy().to_lowercase();
+    let mut plain: HashMap<String, usize> = HashMap::new();
+    for (_, rel) in &matched {
+        *plain
+            .entry(key(&output_dir.join(rel).with_extension(extension(rel))))
+            .or_default() += 1;
+    }
+
+    let mut jobs = Vec::new();
+    let mut outputs: HashMap<String, PathBuf> = HashMap::new();
+    for (path, rel) in matched {
+        let ext = extension(&rel);
+        let mut output = output_dir.join(&rel).with_extension(ext);
+        let same_ext = rel.extension().is_some_and(|e| e.eq_ignore_ascii_case(ext));
+        if plain[&key(&output)] > 1 && !same_ext {
+            let mut name = output_dir.join(&rel).into_os_string();
+            name.push(".");
+            name.push(ext);
+            output = PathBuf::from(name);
+        }
+        if let Some(other) = outputs.insert(key(&output), path.clone()) {
+            return Err(Error::InvalidArgument(format!(
+                "{} and {} would both be written to {}",
+                other.display(),
+                path.display(),
+                output.display()
+            )));
+        }
+        jobs.push(ShrinkJob::new(&path, output, options.clone()));
+    }
+    Ok(jobs)
+}
+
+fn glob_set<S: AsRef<str>>(patterns: &[S]) -> Result<GlobSet> {
+    let mut set = GlobSetBuilder::new();
+    for pattern in patterns {
+        let pattern = pattern.as_ref();
+        let glob = GlobBuilder::new(pattern)
+            .case_insensitive(true)
+            .build()
+            .map_err(|e| Error::InvalidArgument(format!("bad pattern {:?}: {}", pattern, e)))?;
+        set.add(glob);
+    }
+    set.build()
+        .map_err(|e| Error::InvalidArgument(e.to_string()))
+}
+
+fn walk(
+    dir: &Path,
+    recursive: bool,
+    skip_dir: Option<&Path>,
+    files: &mut Vec<PathBuf>,
+) -> std::io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            let skipped =
+                skip_dir.is_some_and(|skip| fs::canonicalize(&path).ok().as_deref() == Some(skip));
+            if recursive && !skipped {
+                walk(&path, recursive, skip_dir, files)?;
+            }
+        } else if path.is_file() {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Outcome of one file in a batch.
+#[derive(Debug)]
+pub struct BatchEntry {
+    pub input: PathBuf,
+    pub output: PathBuf,
+    /// Size of the input file, 0 if it could not be read.
+    pub input_bytes: u64,
+    pub result: Result<ShrinkReport>,
+}
+
+/// Outcome of a whole batch, in job order.
+#[derive(Debug, Default)]
+pub struct BatchReport {
+    pub entries: Vec<BatchEntry>,
+}
+
+impl BatchReport {
+    pub fn failures(&self) -> impl Iterator<Item = &BatchEntry> {
+        self.entries.iter().filter(|e| e.result.is_err())
+    }
+
+    /// Bytes saved across the successful files.
+    pub fn bytes_saved(&self) -> u64 {
+        self.entries
+            .iter()
+            .filter_map(|e| e.result.as_ref().ok())
+            .map(|r| r.input_bytes.saturating_sub(r.output_bytes))
+            .sum()
+    }
+}
+
+/// Shrink one file of a batch, creating its output directory first.
+pub(crate) fn run_one(job: &ShrinkJob) -> BatchEntry {
+    let result = match job.output().parent() {
+        Some(parent) => fs::create_dir_all(parent).map_err(Error::from),
+        None => Ok(()),
+    }
+    .and_then(|()| job.run());
+    BatchEntry {
+        input: job.input().to_path_buf(),
+        output: job.output().to_path_buf(),
+        input_bytes: fs::metadata(job.input()).map_or(0, |m| m.len()),
+        result,
+    }
+}
+
+impl fmt::Display for BatchReport {
+    /// A per-file table followed by a totals line.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: Vec<String> = self
+            .entries
+            .iter()
+            .map(|e| e.input.display().to_string())
+            .collect();
+        let width = names
+            .iter()
+            .map(|n| n.chars().count())
+            .max()
+            .unwrap_or(0)
+            .max(4);
+
+        writeln!(
+            f,
+            "{:<width$}  {:>10}  {:>10}  {:>6}  status",
+            "file", "input", "output", "saved"
+        )?;
+        for (entry, name) in self.entries.iter().zip(&names) {
+            match &entry.result {
+                Ok(r) => {
+                    let saved = 1.0 - r.output_bytes as f64 / r.input_bytes.max(1) as f64;
+                    let mut status = r.passthrough.map_or("ok".to_string(), |p| p.to_string());
+                    if let Some(quality) = &r.quality {
+                        status = format!("{}, {}", status, quality);
+                    }
+                    writeln!(
+                        f,
+                        "{:<width$}  {:>10}  {:>10}  {:>5.1}%  {}",
+                        name,
+                        megabytes(r.input_bytes as f64),
+                        megabytes(r.output_bytes as f64),
+                        saved * 100.0,
+                        status
+                    )?;
+                }
+                Err(e) => {
+                    let first_line = e.to_string().lines().next().unwrap_or_default().to_string();
+                    writeln!(
+                        f,
+                        "{:<width$}  {:>10}  {:>10}  {:>6}  failed: {}",
+                        name,
+                        megabytes(entry.input_bytes as f64),
+                        "-",
+                        "-",
+                        first_line
+                    )?;
+                }
+            }
+        }
+        let failed = self.failures().count();
+        write!(
+            f,
+            "{} file(s), {} ok, {} failed; {} saved",
+            self.entries.len(),
+            self.entries.len() - failed,
+            failed,
+            megabytes(self.bytes_saved() as f64)
+        )
+    }
+}
@@ -0,0 +1,120 @@
+//! Splitting the target size between video and everything else.
+
+use std::fmt;
+
+use crate::audio::AudioSettings;
+use crate::container::Container;
+use crate::probe::MediaInfo;
+use crate::streams::StreamSelection;
+
+/// Subtitle bitrate assumed when ffprobe reports none; a busy SRT track.
+const SUBTITLE_BYTES_PER_SECOND: f64 = 25.0;
+
+/// Bytes a muxer spends per metadata tag on top of its key and value.
+const TAG_OVERHEAD: u64 = 16;
+
+/// Where the bytes of an output of `target_bytes` are expected to go.
+/// Everything not reserved for other streams, metadata and muxing is
+/// left to the video.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Budget {
+    pub target_bytes: u64,
+    /// Seconds of output the budget covers.
+    pub duration: f64,
+    /// The kept audio stream at its encoded bitrate.
+    pub audio_bytes: u64,
+    /// Kept subtitle streams.
+    pub subtitle_bytes: u64,
+    /// Container-level tags copied from the input.
+    pub metadata_bytes: u64,
+    /// Headers, indexes and per-packet framing.
+    pub overhead_bytes: u64,
+}
+
+impl Budget {
+    /// Estimate the non-video costs of shrinking `media` (lasting
+    /// `duration` seconds) into `container` with `streams` kept.
+    pub fn estimate(
+        target_bytes: u64,
+        duration: f64,
+        media: &MediaInfo,
+        streams: &StreamSelection,
+        audio: Option<&AudioSettings>,
+        container: Container,
+    ) -> Self {
+        let stream = |index: u32| media.streams.iter().find(|s| s.index == index);
+
+        // Packets decide the muxing overhead. Frames are counted at the
+        // source rate, which is at least the output rate.
+        let video = stream(streams.video);
+        let mut packets = video
+            .and_then(|v| v.nb_frames)
+            .map(|n| n as f64)
+            .or_else(|| Some(video?.frame_rate()? * duration))
+            .unwrap_or(30.0 * duration);
+
+        let mut audio_bytes = 0;
+        if let Some(settings) = audio {
+            let source_rate = streams.audio.and_then(stream).and_then(|s| s.sample_rate);
+            audio_bytes = (settings.bitrate as f64 / 8.0 * duration) as u64;
+            packets += settings.packet_rate(source_rate) * duration;
+        }
+
+        let mut subtitle_bytes = 0;
+        for sub in streams.subtitles.iter().filter_map(|i| stream(*i)) {
+            subtitle_bytes += match sub.bit_rate {
+                Some(bps) => (bps as f64 / 8.0 * duration) as u64,
+                None => (SUBTITLE_BYTES_PER_SECOND * duration) as u64,
+            };
+            // About one cue every three seconds.
+            packets += sub.nb_frames.map_or(duration / 3.0, |n| n as f64);
+        }
+
+        let metadata_bytes = media
+            .tags
+            .iter()
+            .map(|(k, v)| (k.len() + v.len()) as u64 + TAG_OVERHEAD)
+            .sum();
+
+        let (fixed, per_packet) = container.overhead();
+        Budget {
+            target_bytes,
+            duration,
+            audio_bytes,
+            subtitle_bytes,
+            metadata_bytes,
+            overhead_bytes: fixed + (packets * per_packet as f64) as u64,
+        }
+    }
+
+    /// Bytes spoken for before any video is written.
+    pub fn reserved_bytes(&self) -> u64 {
+        self.audio_bytes + self.subtitle_bytes + self.metadata_bytes + self.overhead_bytes
+    }
+
+    /// What is left for the video, or `None` if nothing is.
+    pub fn video_bytes(&self) -> Option<u64> {
+        self.target_bytes
+            .checked_sub(self.reserved_bytes())
+            .filter(|b| *b > 0)
+    }
+
+    /// Video bitrate (bps) that fills [`Budget::video_bytes`].
+    pub fn video_bitrate(&self) -> Option<u64> {
+        if self.duration <= 0.0 {
+            return None;
+        }
+        Some((self.video_bytes()? as f64 * 8.0 / self.duration) as u64)
+    }
+}
+
+impl fmt::Display for Budget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "budget for {} bytes:", self.target_bytes)?;
+        writeln!(f, "  video      {:>12}", self.video_bytes().unwrap_or(0))?;
+        writeln!(f, "  audio      {:>12}", self.audio_bytes)?;
+        writeln!(f, "  subtitles  {:>12}", self.subtitle_bytes)?;
+        writeln!(f, "  metadata   {:>12}", self.metadata_bytes)?;
+        write!(f, "  container  {:>12}", self.overhead_bytes)
+    }
+}
@@ -0,0 +1,190 @@
+//! Video codecs and how each maps onto ffmpeg encoder arguments.
+
+use std::ffi::OsString;
+use std::fmt;
+use std::path::Path;
+
+use crate::command::Options;
+
+/// How much encoder time is spent per frame to save bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Preset {
+    Fast,
+    /// Comparable to x264's `medium`.
+    #[default]
+    Medium,
+    Slow,
+}
+
+impl fmt::Display for Preset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Preset::Fast => "fast",
+            Preset::Medium => "medium",
+            Preset::Slow => "slow",
+        })
+    }
+}
+
+/// A video codec together with the ffmpeg encoder that produces it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum VideoCodec {
+    /// H.264 via libx264.
+    #[default]
+    H264,
+    /// H.265/HEVC via libx265, tagged `hvc1` so Apple players accept it.
+    H265,
+    /// AV1 via SVT-AV1, the fast AV1 encoder.
+    Av1Svt,
+    /// AV1 via libaom, slower but supports two-pass.
+    Av1Aom,
+    /// VP9 via libvpx.
+    Vp9,
+}
+
+impl VideoCodec {
+    /// ffmpeg encoder name, as listed by `ffmpeg -encoders`.
+    pub fn encoder(self) -> &'static str {
+        match self {
+            VideoCodec::H264 => "libx264",
+            VideoCodec::H265 => "libx265",
+            VideoCodec::Av1Svt => "libsvtav1",
+            VideoCodec::Av1Aom => "libaom-av1",
+            VideoCodec::Vp9 => "libvpx-vp9",
+        }
+    }
+
+    /// Whether ffmpeg can drive this encoder in two passes. The libsvtav1
+    /// wrapper has no stats-file support, so it always encodes in one.
+    pub fn supports_two_pass(self) -> bool {
+        self != VideoCodec::Av1Svt
+    }
+
+    /// CRF roughly equivalent to x264's 32 on each encoder's own scale.
+    pub fn default_crf(self) -> u32 {
+        match self {
+            VideoCodec::H264 => 32,
+            VideoCodec::H265 => 34,
+            VideoCodec::Av1Svt | VideoCodec::Av1Aom => 45,
+            VideoCodec::Vp9 => 42,
+        }
+    }
+
+    /// CRFs the target-quality search tries, from near-transparent to
+    /// the encoder's lowest quality.
+    pub fn crf_range(self) -> (u32, u32) {
+        match self {
+            VideoCodec::H264 | VideoCodec::H265 => (12, 51),
+            VideoCodec::Av1Svt | VideoCodec::Av1Aom | VideoCodec::Vp9 => (15, 63),
+        }
+    }
+
+    /// Bits per pixel per frame below which this codec's output turns
+    /// visibly blocky; the automatic resolution policy stays above it.
+    pub fn bits_per_pixel(self) -> f64 {
+        match self {
+            VideoCodec::H264 => 0.08,
+            VideoCodec::H265 | VideoCodec::Vp9 => 0.055,
+            VideoCodec::Av1Svt | VideoCodec::Av1Aom => 0.045,
+        }
+    }
+
+    /// Encoder options for `preset`. libaom and libvpx take a `cpu-used`
+    /// speed instead of a named preset, SVT-AV1 a numbered one.
+    pub(crate) fn preset_options(self, preset: Preset) -> Options {
+        let speed = |fast: &'static str, medium: &'static str, slow: &'static str| match preset {
+            Preset::Fast => fast,
+            Preset::Medium => medium,
+            Preset::Slow => slow,
+        };
+        let options: Vec<(&'static str, &str)> = match self {
+            VideoCodec::H264 | VideoCodec::H265 => {
+                vec![("preset", speed("fast", "medium", "slow"))]
+            }
+            VideoCodec::Av1Svt => vec![("preset", speed("10", "8", "6"))],
+            VideoCodec::Av1Aom => vec![("cpu-used", speed("6", "4", "3")), ("row-mt", "1")],
+            VideoCodec::Vp9 => vec![
+                ("deadline", "good"),
+                ("cpu-used", speed("4", "2", "1")),
+                ("row-mt", "1"),
+            ],
+        };
+        options
+            .into_iter()
+            .map(|(k, v)| (k, OsString::from(v)))
+            .collect()
+    }
+
+    /// Average-bitrate rate control. The x26x encoders also get a VBV cap
+    /// at the same rate; SVT-AV1 rejects `-maxrate` outside CRF mode.
+    pub(crate) fn bitrate_options(self, v_bitrate: u64) -> Options {
+        let mut options = vec![("b", kbps(v_bitrate))];
+        if matches!(self, VideoCodec::H264 | VideoCodec::H265) {
+            options.extend([
+                ("maxrate", kbps(v_bitrate)),
+                ("bufsize", kbps(v_bitrate * 2)),
+            ]);
+        }
+        options
+    }
+
+    /// Constant-quality rate control. libvpx and libaom only treat `-crf`
+    /// as pure quality when the target bitrate is zeroed.
+    pub(crate) fn crf_options(self, crf: u32) -> Options {
+        let mut options = vec![("crf", crf.to_string().into())];
+        if matches!(self, VideoCodec::Vp9 | VideoCodec::Av1Aom) {
+            options.push(("b", "0".into()));
+        }
+        options
+    }
+
+    /// Constant quality capped at `max_bitrate`. libvpx and libaom read
+    /// `-b:v` alongside `-crf` as the cap (constrained quality); the others
+    /// take a VBV-style `-maxrate`.
+    pub(crate) fn capped_crf_options(self, crf: u32, max_bitrate: u64) -> Options {
+        let mut options = vec![("crf", crf.to_string().into())];
+        match self {
+            VideoCodec::Vp9 | VideoCodec::Av1Aom => options.push(("b", kbps(max_bitrate))),
+            VideoCodec::H264 | VideoCodec::H265 => options.extend([
+                ("maxrate", kbps(max_bitrate)),
+                ("bufsize", kbps(max_bitrate * 2)),
+            ]),
+            VideoCodec::Av1Svt => options.push(("maxrate", kbps(max_bitrate))),
+        }
+        options
+    }
+
+    /// Options selecting pass 1 or 2 with stats kept under `passlog`.
+    /// libx265 ignores `-pass`, so it takes its stats file via x265-params.
+    /// Those split on `:`, which a Windows path contains, so libx265 only
+    /// gets the file name and must run in the passlog's directory.
+    pub(crate) fn pass_options(self, pass: u8, passlog: &Path) -> Options {
+        if self == VideoCodec::H265 {
+            let mut params = OsString::from(format!("pass={}:stats=", pass));
+            params.push(passlog.file_name().unwrap_or(passlog.as_os_str()));
+            return vec![("x265-params", params)];
+        }
+        vec![
+            ("pass", pass.to_string().into()),
+            ("passlogfile", passlog.into()),
+        ]
+    }
+
+    /// Stream tag needed for MP4 playback compatibility.
+    pub(crate) fn tag_options(self) -> Options {
+        match self {
+            VideoCodec::H265 => vec![("tag", "hvc1".into())],
+            _ => Vec::new(),
+        }
+    }
+}
+
+impl fmt::Display for VideoCodec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.encoder())
+    }
+}
+
+fn kbps(bps: u64) -> OsString {
+    format!("{}k", bps / 1000).into()
+}
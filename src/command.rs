@@ -0,0 +1,477 @@
+//! Typed ffmpeg and ffprobe command lines.
+//!
+//! Callers describe inputs, stream maps, per-stream codec options, filters
+//! and muxer settings; [`Command::args`] renders them to an argv in the
+//! order ffmpeg expects.
+
+use std::ffi::OsString;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// Options as `(name, value)` pairs, the name without its leading `-`.
+pub(crate) type Options = Vec<(&'static str, OsString)>;
+
+/// The stream type an option applies to, rendered as a `:v`, `:a` or
+/// `:s` stream specifier.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum StreamType {
+    Video,
+    Audio,
+    Subtitle,
+}
+
+impl StreamType {
+    fn specifier(self) -> char {
+        match self {
+            StreamType::Video => 'v',
+            StreamType::Audio => 'a',
+            StreamType::Subtitle => 's',
+        }
+    }
+}
+
+/// One filter of a chain, e.g. `scale=640:360`.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Filter {
+    name: &'static str,
+    args: Vec<String>,
+}
+
+impl Filter {
+    pub fn new(name: &'static str) -> Self {
+        Filter {
+            name,
+            args: Vec::new(),
+        }
+    }
+
+    pub fn arg(mut self, value: impl ToString) -> Self {
+        self.args.push(value.to_string());
+        self
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name)?;
+        if !self.args.is_empty() {
+            write!(f, "={}", self.args.join(":"))?;
+        }
+        Ok(())
+    }
+}
+
+/// An input file and the options that apply to reading it.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Input {
+    options: Options,
+    path: OsString,
+}
+
+impl Input {
+    pub fn new(path: impl Into<OsString>) -> Self {
+        Input {
+            options: Vec::new(),
+            path: path.into(),
+        }
+    }
+
+    /// An option applying to this input, such as `ss 30` to start 30 s in.
+    pub fn option(mut self, name: &'static str, value: impl Into<OsString>) -> Self {
+        self.options.push((name, value.into()));
+        self
+    }
+}
+
+/// The output file and everything that decides its contents.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Output {
+    maps: Vec<String>,
+    stream_options: Vec<(StreamType, &'static str, OsString)>,
+    video_filters: Vec<Filter>,
+    disabled: Vec<StreamType>,
+    format: Option<&'static str>,
+    muxer_options: Options,
+    path: OsString,
+}
+
+impl Output {
+    pub fn new(path: impl Into<OsString>) -> Self {
+        Output {
+            maps: Vec::new(),
+            stream_options: Vec::new(),
+            video_filters: Vec::new(),
+            disabled: Vec::new(),
+            format: None,
+            muxer_options: Vec::new(),
+            path: path.into(),
+        }
+    }
+
+    /// Add `-map` for an input stream such as `0:1`.
+    pub fn map(mut self, specifier: impl Into<String>) -> Self {
+        self.maps.push(specifier.into());
+        self
+    }
+
+    /// The encoder for every stream of `kind`, or `copy`.
+    pub fn codec(self, kind: StreamType, encoder: &'static str) -> Self {
+        self.stream_option(kind, "c", encoder)
+    }
+
+    pub fn stream_option(
+        mut self,
+        kind: StreamType,
+        name: &'static str,
+        value: impl Into<OsString>,
+    ) -> Self {
+        self.stream_options.push((kind, name, value.into()));
+        self
+    }
+
+    pub fn stream_options(self, kind: StreamType, options: Options) -> Self {
+        options.into_iter().fold(self, |out, (name, value)| {
+            out.stream_option(kind, name, value)
+        })
+    }
+
+    /// Append a filter to the video filter chain.
+    pub fn video_filter(mut self, filter: Filter) -> Self {
+        self.video_filters.push(filter);
+        self
+    }
+
+    /// Write no streams of `kind` (`-an`, `-sn`, ...).
+    pub fn disable(mut self, kind: StreamType) -> Self {
+        self.disabled.push(kind);
+        self
+    }
+
+    /// Muxer name for `-f`.
+    pub fn format(mut self, muxer: &'static str) -> Self {
+        self.format = Some(muxer);
+        self
+    }
+
+    pub fn muxer_options(mut self, options: Options) -> Self {
+        self.muxer_options.extend(options);
+        self
+    }
+
+    fn render(&self, args: &mut Vec<OsString>) {
+        for map in &self.maps {
+            args.extend(["-map".into(), map.into()]);
+        }
+        for (kind, name, value) in &self.stream_options {
+            args.push(format!("-{}:{}", name, kind.specifier()).into());
+            args.push(value.clone());
+        }
+        if !self.video_filters.is_empty() {
+            let chain: Vec<String> = self.video_filters.iter().map(Filter::to_string).collect();
+            args.extend(["-vf".into(), chain.join(",").into()]);
+        }
+        for kind in &self.disabled {
+            args.push(format!("-{}n", kind.specifier()).into());
+        }
+        if let Some(format) = self.format {
+            args.extend(["-f".into(), format.into()]);
+        }
+        render_options(&self.muxer_options, args);
+        args.push(self.path.clone());
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Program {
+    Ffmpeg,
+    Ffprobe,
+}
+
+/// An ffmpeg or ffprobe invocation.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Command {
+    program: Program,
+    global: Vec<OsString>,
+    inputs: Vec<Input>,
+    output: Option<Output>,
+    current_dir: Option<PathBuf>,
+}
+
+impl Command {
+    pub fn ffmpeg() -> Self {
+        Command::new(Program::Ffmpeg)
+    }
+
+    /// ffprobe takes its input as a bare path and writes no output file.
+    pub fn ffprobe() -> Self {
+        Command::new(Program::Ffprobe)
+    }
+
+    fn new(program: Program) -> Self {
+        Command {
+            program,
+            global: Vec::new(),
+            inputs: Vec::new(),
+            output: None,
+            current_dir: None,
+        }
+    }
+
+    /// Executable name, looked up on `PATH`.
+    pub fn program(&self) -> &'static str {
+        match self.program {
+            Program::Ffmpeg => "ffmpeg",
+            Program::Ffprobe => "ffprobe",
+        }
+    }
+
+    /// A global option without a value, such as `y`.
+    pub fn flag(mut self, name: &'static str) -> Self {
+        self.global.push(format!("-{}", name).into());
+        self
+    }
+
+    /// A global option with a value, such as `loglevel error`.
+    pub fn option(mut self, name: &'static str, value: impl Into<OsString>) -> Self {
+        self.global.push(format!("-{}", name).into());
+        self.global.push(value.into());
+        self
+    }
+
+    pub fn input(mut self, input: Input) -> Self {
+        self.inputs.push(input);
+        self
+    }
+
+    pub fn output(mut self, output: Output) -> Self {
+        self.output = Some(output);
+        self
+    }
+
+    /// Run in `dir` instead of the caller's working directory.
+    pub fn current_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.current_dir = Some(dir.into());
+        self
+    }
+
+    pub fn dir(&self) -> Option<&Path> {
+        self.current_dir.as_deref()
+    }
+
+    /// The argv after the program name.
+    pub fn args(&self) -> Vec<OsString> {
+        let mut args = self.global.clone();
+        for input in &self.inputs {
+            render_options(&input.options, &mut args);
+            if self.program == Program::Ffmpeg {
+                args.push("-i".into());
+            }
+            args.push(input.path.clone());
+        }
+        if let Some(output) = &self.output {
+            output.render(&mut args);
+        }
+        args
+    }
+
+    /// The whole command as one line a shell reads back unchanged: POSIX
+    /// single quotes, or double quotes for `cmd.exe` on Windows.
+    pub fn shell_line(&self) -> String {
+        let mut line = match &self.current_dir {
+            Some(dir) => format!("cd {} && ", shell_quote(&dir.to_string_lossy())),
+            None => String::new(),
+        };
+        line.push_str(self.program());
+        for arg in self.args() {
+            line.push(' ');
+            line.push_str(&shell_quote(&arg.to_string_lossy()));
+        }
+        line
+    }
+}
+
+fn render_options(options: &Options, args: &mut Vec<OsString>) {
+    for (name, value) in options {
+        args.push(format!("-{}", name).into());
+        args.push(value.clone());
+    }
+}
+
+pub(crate) fn shell_quote(arg: &str) -> String {
+    let plain = |c: char| c.is_ascii_alphanumeric() || "-_./:=+,@%".contains(c);
+    if !arg.is_empty() && arg.chars().all(plain) {
+        arg.to_string()
+    } else if cfg!(windows) {
+        format!("\"{}\"", arg.replace('"', "\"\""))
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn argv(command: &Command) -> Vec<String> {
+        command
+            .args()
+            .iter()
+            .map(|a| a.to_string_lossy().into_owned())
+            .collect()
+    }
+
+    fn base() -> Command {
+        Command::ffmpeg().flag("y").option("loglevel", "error")
+    }
+
+    #[test]
+    fn single_pass_renders_in_ffmpeg_order() {
+        let output = Output::new("out.mp4")
+            .map("0:0")
+            .map("0:1")
+            .codec(StreamType::Video, "libx264")
+            .stream_option(StreamType::Video, "b", "500k")
+            .codec(StreamType::Audio, "aac")
+            .video_filter(Filter::new("fps").arg(24))
+            .video_filter(Filter::new("scale").arg(640).arg(360))
+            .disable(StreamType::Subtitle)
+            .format("mp4")
+            .muxer_options(vec![("movflags", "+faststart".into())]);
+        let command = base()
+            .input(Input::new("in.mp4").option("ss", "30"))
+            .output(output);
+
+        assert_eq!(
+            argv(&command),
+            [
+                "-y",
+                "-loglevel",
+                "error",
+                "-ss",
+                "30",
+                "-i",
+                "in.mp4",
+                "-map",
+                "0:0",
+                "-map",
+                "0:1",
+                "-c:v",
+                "libx264",
+                "-b:v",
+                "500k",
+                "-c:a",
+                "aac",
+                "-vf",
+                "fps=24,scale=640:360",
+                "-sn",
+                "-f",
+                "mp4",
+                "-movflags",
+                "+faststart",
+                "out.mp4",
+            ]
+        );
+    }
+
+    #[test]
+    fn two_pass_renders_a_null_first_pass() {
+        let pass = |n: u8, output: Output| {
+            base().input(Input::new("in.mp4")).output(
+                output
+                    .codec(StreamType::Video, "libx264")
+                    .stream_option(StreamType::Video, "pass", n.to_string())
+                    .stream_option(StreamType::Video, "passlogfile", "ffmpeg2pass"),
+            )
+        };
+        let null = Output::new("/dev/null")
+            .disable(StreamType::Audio)
+            .format("null");
+        let first = pass(1, null).current_dir("log");
+        let second = pass(2, Output::new("out.mp4").format("mp4"));
+
+        let tail = [
+            "-c:v",
+            "libx264",
+            "-pass:v",
+            "1",
+            "-passlogfile:v",
+            "ffmpeg2pass",
+            "-an",
+            "-f",
+            "null",
+            "/dev/null",
+        ];
+        assert_eq!(
+            argv(&first)[3..],
+            ["-i", "in.mp4"].into_iter().chain(tail).collect::<Vec<_>>()
+        );
+        assert_eq!(first.dir(), Some(Path::new("log")));
+        assert!(first.shell_line().starts_with("cd log && ffmpeg -y "));
+        assert_eq!(
+            argv(&second)[5..],
+            [
+                "-c:v",
+                "libx264",
+                "-pass:v",
+                "2",
+                "-passlogfile:v",
+                "ffmpeg2pass",
+                "-f",
+                "mp4",
+                "out.mp4",
+            ]
+        );
+    }
+
+    #[test]
+    fn remux_copies_streams() {
+        let output = Output::new("out.mkv")
+            .map("0:0")
+            .codec(StreamType::Video, "copy")
+            .codec(StreamType::Audio, "copy")
+            .format("matroska");
+        let command = base().input(Input::new("in.mp4")).output(output);
+
+        assert_eq!(
+            argv(&command)[3..],
+            [
+                "-i", "in.mp4", "-map", "0:0", "-c:v", "copy", "-c:a", "copy", "-f", "matroska",
+                "out.mkv",
+            ]
+        );
+    }
+
+    #[test]
+    fn ffprobe_takes_a_bare_path() {
+        let command = Command::ffprobe()
+            .option("v", "error")
+            .input(Input::new("in.mp4"));
+        assert_eq!(argv(&command), ["-v", "error", "in.mp4"]);
+    }
+
+    #[test]
+    fn filter_without_args_is_its_name() {
+        assert_eq!(Filter::new("null").to_string(), "null");
+        assert_eq!(
+            Filter::new("scale").arg(-2).arg(720).to_string(),
+            "scale=-2:720"
+        );
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn shell_quote_escapes_spaces_and_quotes() {
+        assert_eq!(shell_quote("in.mp4"), "in.mp4");
+        assert_eq!(shell_quote(""), "''");
+        assert_eq!(shell_quote("my clip.mp4"), "'my clip.mp4'");
+        assert_eq!(shell_quote("it's.mp4"), "'it'\\''s.mp4'");
+        let line = base()
+            .input(Input::new("my dir/it's.mp4"))
+            .output(Output::new("out.mp4"))
+            .shell_line();
+        assert_eq!(
+            line,
+            "ffmpeg -y -loglevel error -i 'my dir/it'\\''s.mp4' out.mp4"
+        );
+    }
+}
@@ -0,0 +1,83 @@
+//! How hard an input is to compress, measured on sample encodes.
+
+use std::fmt;
+
+use crate::codec::{Preset, VideoCodec};
+
+/// Share of a codec's bits-per-pixel budget that typical content needs at
+/// the codec's default CRF. Below it are talking heads and screencasts,
+/// above it sports, water and film grain.
+const TYPICAL_SHARE: f64 = 0.3;
+
+/// Bounds on [`Complexity::factor`], so one odd sample cannot swing the
+/// output shape too far.
+const FACTOR_RANGE: (f64, f64) = (0.5, 2.0);
+
+/// How hard the input is to compress, from samples encoded at the codec's
+/// default CRF.
+///
+/// Hard content gives up resolution before frames, since its motion
+/// suffers most from dropped frames; easy content gives up frames first,
+/// having little motion to lose.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Complexity {
+    /// Bits per pixel per frame the samples needed.
+    pub bits_per_pixel: f64,
+    /// `bits_per_pixel` against typical content for the codec: below 1
+    /// is easy, above 1 hard. Clamped to 0.5..=2.
+    pub factor: f64,
+}
+
+impl Complexity {
+    /// Complexity of `bytes` of video covering `seconds` of `pixels`-sized
+    /// frames at `fps`, encoded with `codec`.
+    pub fn measure(bytes: u64, seconds: f64, pixels: u64, fps: f64, codec: VideoCodec) -> Self {
+        let frames = seconds * fps;
+        let bits_per_pixel = if frames > 0.0 && pixels > 0 {
+            bytes as f64 * 8.0 / (pixels as f64 * frames)
+        } else {
+            0.0
+        };
+        let typical = codec.bits_per_pixel() * TYPICAL_SHARE;
+        Complexity {
+            bits_per_pixel,
+            factor: (bits_per_pixel / typical).clamp(FACTOR_RANGE.0, FACTOR_RANGE.1),
+        }
+    }
+
+    /// Per-pixel budget the resolution is chosen against: the codec's,
+    /// raised for hard content and lowered for easy content.
+    pub fn scale_bits_per_pixel(self, codec: VideoCodec) -> f64 {
+        codec.bits_per_pixel() * self.factor
+    }
+
+    /// Per-pixel budget frames are dropped against: the codec's, lowered
+    /// for hard content so it keeps its motion and raised for easy content.
+    pub fn fps_bits_per_pixel(self, codec: VideoCodec) -> f64 {
+        codec.bits_per_pixel() / self.factor
+    }
+
+    /// Slower presets for hard content, where a wider motion search and
+    /// more reference frames pay off; faster ones for easy content, which
+    /// gains little from them.
+    pub fn preset(self) -> Preset {
+        if self.factor >= 1.5 {
+            Preset::Slow
+        } else if self.factor <= 0.75 {
+            Preset::Fast
+        } else {
+            Preset::Medium
+        }
+    }
+}
+
+impl fmt::Display for Complexity {
+    /// E.g. `0.031 bits/pixel, 1.29x typical`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:.3} bits/pixel, {:.2}x typical",
+            self.bits_per_pixel, self.factor
+        )
+    }
+}
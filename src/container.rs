@@ -0,0 +1,112 @@
+//! Output containers and which codecs they can carry.
+
+use std::fmt;
+use std::path::Path;
+
+use crate::codec::VideoCodec;
+use crate::command::Options;
+
+/// Output file format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Container {
+    #[default]
+    Mp4,
+    WebM,
+    Mkv,
+    Mov,
+}
+
+impl Container {
+    /// Container named by a path's extension, ignoring case.
+    pub fn from_path(path: &Path) -> Option<Container> {
+        let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+        match ext.as_str() {
+            "mp4" | "m4v" => Some(Container::Mp4),
+            "webm" => Some(Container::WebM),
+            "mkv" => Some(Container::Mkv),
+            "mov" => Some(Container::Mov),
+            _ => None,
+        }
+    }
+
+    /// Usual file extension, without the dot.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Container::Mp4 => "mp4",
+            Container::WebM => "webm",
+            Container::Mkv => "mkv",
+            Container::Mov => "mov",
+        }
+    }
+
+    /// ffmpeg muxer name for `-f`.
+    pub fn muxer(self) -> &'static str {
+        match self {
+            Container::Mp4 => "mp4",
+            Container::WebM => "webm",
+            Container::Mkv => "matroska",
+            Container::Mov => "mov",
+        }
+    }
+
+    /// Whether players can be expected to handle `codec` in this container.
+    pub fn supports(self, codec: VideoCodec) -> bool {
+        match self {
+            Container::Mp4 | Container::Mkv => true,
+            Container::WebM => matches!(
+                codec,
+                VideoCodec::Vp9 | VideoCodec::Av1Svt | VideoCodec::Av1Aom
+            ),
+            Container::Mov => matches!(codec, VideoCodec::H264 | VideoCodec::H265),
+        }
+    }
+
+    /// Whether this is one of the ISO/QuickTime formats that need the
+    /// `moov` atom moved up front and `hvc1` tagging.
+    pub fn is_isobmff(self) -> bool {
+        matches!(self, Container::Mp4 | Container::Mov)
+    }
+
+    /// Rough muxing cost as (fixed bytes, bytes per packet). MP4 sample
+    /// tables cost about a dozen bytes per sample once sizes, offsets and
+    /// timestamps are counted; Matroska block headers plus clusters and
+    /// cues come to a little more.
+    pub(crate) fn overhead(self) -> (u64, u64) {
+        if self.is_isobmff() {
+            (2048, 12)
+        } else {
+            (1024, 14)
+        }
+    }
+
+    /// Encoder for kept subtitle streams. Matroska stores any format, so
+    /// its subtitles are copied.
+    pub(crate) fn subtitle_codec(self) -> &'static str {
+        match self {
+            Container::Mp4 | Container::Mov => "mov_text",
+            Container::WebM => "webvtt",
+            Container::Mkv => "copy",
+        }
+    }
+
+    /// Muxer options for the final output: MP4 and MOV get `+faststart`
+    /// so playback can begin before the whole file is downloaded.
+    pub(crate) fn muxer_options(self) -> Options {
+        if self.is_isobmff() {
+            vec![("movflags", "+faststart".into())]
+        } else {
+            Vec::new()
+        }
+    }
+}
+
+impl fmt::Display for Container {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Container::Mp4 => "MP4",
+            Container::WebM => "WebM",
+            Container::Mkv => "MKV",
+            Container::Mov => "MOV",
+        })
+    }
+}
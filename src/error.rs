@@ -0,0 +1,129 @@
+//! Error type shared by the library and the CLI.
+
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+/// Everything that can stop a shrink job.
+///
+/// Each variant maps to a distinct process exit code via [`Error::exit_code`]:
+///
+/// | code | variant                      |
+/// |------|------------------------------|
+/// | 1    | [`Error::Io`]                |
+/// | 2    | [`Error::InvalidArgument`]   |
+/// | 3    | [`Error::MissingBinary`]     |
+/// | 4    | [`Error::Input`]             |
+/// | 5    | [`Error::Probe`]             |
+/// | 6    | [`Error::Encoder`]           |
+/// | 7    | [`Error::TargetUnreachable`] |
+/// | 8    | [`Error::MissingEncoder`]    |
+/// | 9    | [`Error::MissingFilter`]     |
+///
+/// Code 2 is shared with clap's own command-line usage errors.
+#[derive(Debug)]
+pub enum Error {
+    /// `ffmpeg` or `ffprobe` is not on `PATH`.
+    MissingBinary { name: &'static str },
+    /// The local ffmpeg build lacks the requested encoder.
+    MissingEncoder { name: &'static str },
+    /// The local ffmpeg build lacks a filter the job needs, e.g. libvmaf.
+    MissingFilter { name: &'static str },
+    /// The input file cannot be opened.
+    Input { path: PathBuf, source: io::Error },
+    /// ffprobe failed or printed something we could not parse.
+    Probe { path: PathBuf, message: String },
+    /// ffmpeg exited unsuccessfully; `stderr_tail` holds its last lines.
+    Encoder {
+        code: Option<i32>,
+        stderr_tail: String,
+    },
+    /// The output cannot be brought under `target_bytes`. `output_bytes` is
+    /// the size produced, or the predicted size when known before encoding.
+    TargetUnreachable {
+        target_bytes: u64,
+        output_bytes: u64,
+    },
+    /// An option value that cannot be used, e.g. a malformed glob pattern.
+    InvalidArgument(String),
+    /// Any other I/O failure, e.g. writing the output.
+    Io(io::Error),
+}
+
+/// Result alias using [`Error`].
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    /// Process exit code for this error; see the table on [`Error`].
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Error::Io(_) => 1,
+            Error::InvalidArgument(_) => 2,
+            Error::MissingBinary { .. } => 3,
+            Error::Input { .. } => 4,
+            Error::Probe { .. } => 5,
+            Error::Encoder { .. } => 6,
+            Error::TargetUnreachable { .. } => 7,
+            Error::MissingEncoder { .. } => 8,
+            Error::MissingFilter { .. } => 9,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::MissingBinary { name } => {
+                write!(f, "{} not found; install it and add it to PATH", name)
+            }
+            Error::MissingEncoder { name } => {
+                write!(f, "this ffmpeg build has no {} encoder", name)
+            }
+            Error::MissingFilter { name } => {
+                write!(f, "this ffmpeg build has no {} filter", name)
+            }
+            Error::Input { path, source } => {
+                write!(f, "cannot read input {}: {}", path.display(), source)
+            }
+            Error::Probe { path, message } => {
+                write!(f, "cannot probe {}: {}", path.display(), message)
+            }
+            Error::Encoder { code, stderr_tail } => {
+                match code {
+                    Some(code) => write!(f, "ffmpeg failed with exit code {}", code)?,
+                    None => write!(f, "ffmpeg was terminated by a signal")?,
+                }
+                if !stderr_tail.is_empty() {
+                    write!(f, ":\n{}", stderr_tail)?;
+                }
+                Ok(())
+            }
+            Error::TargetUnreachable {
+                target_bytes,
+                output_bytes,
+            } => write!(
+                f,
+                "cannot fit target of {} bytes (output is {} bytes)",
+                target_bytes, output_bytes
+            ),
+            Error::InvalidArgument(message) => f.write_str(message),
+            Error::Io(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Input { source, .. } => Some(source),
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
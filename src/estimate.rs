@@ -0,0 +1,174 @@
+//! Predicting a full encode from sample windows of it.
+
+use std::fmt;
+
+/// Relative margin below which an estimate counts as high confidence,
+/// and below which as medium.
+const CONFIDENCE_MARGINS: (f64, f64) = (0.1, 0.25);
+
+/// Bytes and encode seconds of one sample window.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct SampleRun {
+    /// Seconds of input the window covers.
+    pub seconds: f64,
+    /// Size of the encoded video.
+    pub bytes: u64,
+    /// Wall-clock seconds the encode took.
+    pub encode_seconds: f64,
+}
+
+/// Whether an estimated output fits `target_bytes`, allowing for the
+/// estimate's margin.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    /// Fits even at the top of the margin.
+    Fits,
+    /// The target lies within the margin.
+    Borderline,
+    /// Over the target even at the bottom of the margin.
+    Over,
+}
+
+/// A full encode extrapolated from sample encodes at the planned settings.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Estimate {
+    /// The size aimed at; `None` in modes that do not target a size.
+    pub target_bytes: Option<u64>,
+    /// Number of sample windows encoded.
+    pub samples: usize,
+    /// Seconds of input the samples covered.
+    pub sampled_seconds: f64,
+    /// Seconds of input in total.
+    pub duration: f64,
+    /// Predicted video bytes.
+    pub video_bytes: u64,
+    /// Bytes the budget reserves for audio, subtitles, metadata and muxing.
+    pub reserved_bytes: u64,
+    /// Predicted wall-clock seconds of the full encode, every pass included.
+    pub encode_seconds: f64,
+    /// Relative error the size prediction is likely within, from how much
+    /// the samples' bitrates disagree; 0.2 is ±20%.
+    pub margin: f64,
+}
+
+impl Estimate {
+    /// Scale `runs` up to `duration` seconds of input (the sampled length
+    /// if unknown) encoded in `passes` passes.
+    pub(crate) fn extrapolate(
+        target_bytes: Option<u64>,
+        runs: &[SampleRun],
+        duration: Option<f64>,
+        reserved_bytes: u64,
+        passes: u32,
+    ) -> Self {
+        let sampled_seconds: f64 = runs.iter().map(|r| r.seconds).sum();
+        let duration = duration.unwrap_or(sampled_seconds);
+        let scale = if sampled_seconds > 0.0 {
+            duration / sampled_seconds
+        } else {
+            1.0
+        };
+        let bytes: u64 = runs.iter().map(|r| r.bytes).sum();
+        let encode_seconds: f64 = runs.iter().map(|r| r.encode_seconds).sum();
+        Estimate {
+            target_bytes,
+            samples: runs.len(),
+            sampled_seconds,
+            duration,
+            video_bytes: (bytes as f64 * scale) as u64,
+            reserved_bytes,
+            encode_seconds: encode_seconds * scale * passes as f64,
+            margin: margin(runs, sampled_seconds / duration.max(f64::MIN_POSITIVE)),
+        }
+    }
+
+    /// Predicted size of the whole output.
+    pub fn output_bytes(&self) -> u64 {
+        self.video_bytes + self.reserved_bytes
+    }
+
+    /// `high`, `medium` or `low`, by the size margin.
+    pub fn confidence(&self) -> &'static str {
+        if self.margin < CONFIDENCE_MARGINS.0 {
+            "high"
+        } else if self.margin < CONFIDENCE_MARGINS.1 {
+            "medium"
+        } else {
+            "low"
+        }
+    }
+
+    /// Whether the predicted output fits `target_bytes`; `None` without
+    /// a target.
+    pub fn verdict(&self) -> Option<Verdict> {
+        let bytes = self.output_bytes() as f64;
+        let target = self.target_bytes? as f64;
+        Some(if bytes * (1.0 + self.margin) <= target {
+            Verdict::Fits
+        } else if bytes * (1.0 - self.margin) > target {
+            Verdict::Over
+        } else {
+            Verdict::Borderline
+        })
+    }
+}
+
+/// Twice the standard error of the samples' mean bitrate, relative to
+/// that mean, shrunk by the share of the input they already cover. One
+/// window gives no spread to judge by, so it counts as ±50% unless it
+/// is the whole input.
+fn margin(runs: &[SampleRun], covered: f64) -> f64 {
+    let unsampled = (1.0 - covered).clamp(0.0, 1.0);
+    let rates: Vec<f64> = runs
+        .iter()
+        .filter(|r| r.seconds > 0.0)
+        .map(|r| r.bytes as f64 / r.seconds)
+        .collect();
+    if rates.len() < 2 {
+        return 0.5 * unsampled;
+    }
+    let n = rates.len() as f64;
+    let mean = rates.iter().sum::<f64>() / n;
+    if mean <= 0.0 {
+        return 0.5 * unsampled;
+    }
+    let variance = rates.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / (n - 1.0);
+    2.0 * variance.sqrt() / mean / n.sqrt() * unsampled
+}
+
+impl fmt::Display for Estimate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "estimate from {} sample(s) covering {:.1}s of {:.1}s:",
+            self.samples, self.sampled_seconds, self.duration
+        )?;
+        writeln!(
+            f,
+            "  output       {:>12} bytes (±{:.0}%, {} confidence)",
+            self.output_bytes(),
+            self.margin * 100.0,
+            self.confidence()
+        )?;
+        writeln!(f, "  video        {:>12} bytes", self.video_bytes)?;
+        write!(f, "  encode time  {:>12.0} s", self.encode_seconds)?;
+        let (Some(target), Some(verdict)) = (self.target_bytes, self.verdict()) else {
+            return Ok(());
+        };
+        let needed = 1.0 - target as f64 / self.output_bytes().max(1) as f64;
+        match verdict {
+            Verdict::Fits => write!(f, "\n{} bytes is achievable", target),
+            Verdict::Borderline => write!(
+                f,
+                "\n{} bytes is borderline, within the estimate's margin",
+                target
+            ),
+            Verdict::Over => write!(
+                f,
+                "\n{} bytes is not achievable at these settings; it needs {:.0}% fewer bytes",
+                target,
+                needed * 100.0
+            ),
+        }
+    }
+}
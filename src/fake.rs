@@ -0,0 +1,215 @@
+//! A scripted stand-in for ffmpeg, for tests. Built with the
+//! `test-support` feature.
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::path::Path;
+use std::sync::Mutex;
+
+use crate::backend::{Encoder, Prober};
+use crate::error::{Error, Result};
+use crate::ffmpeg::EncodePlan;
+use crate::probe::MediaInfo;
+use crate::quality::QualityScores;
+use crate::ratecontrol::RateControl;
+use crate::sample::Sample;
+use crate::streams::StreamSelection;
+use crate::ShrinkJob;
+
+/// Every encoder the crate can ask for.
+const FAKE_ENCODERS: &[&str] = &[
+    "libx264",
+    "libx265",
+    "libsvtav1",
+    "libaom-av1",
+    "libvpx-vp9",
+    "aac",
+    "libfdk_aac",
+    "libopus",
+];
+
+/// One call made to a [`FakeBackend`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FakeCall {
+    /// An encode at this video bitrate (or cap) in bps.
+    Encode {
+        video_bitrate: u64,
+    },
+    Remux,
+    MeasureQuality,
+    /// A sample encoded and scored at this CRF.
+    SampleQuality {
+        crf: u32,
+        sample: Sample,
+    },
+    /// A sample encoded to measure its size.
+    SampleSize {
+        sample: Sample,
+    },
+}
+
+/// A stand-in for ffmpeg that probes every input as the same canned
+/// [`MediaInfo`] and writes outputs of scripted sizes, so planning and
+/// the convergence loop can be tested without media or ffmpeg.
+///
+/// Each encode or remux takes the next size from
+/// [`FakeBackend::output_sizes`]. Once those run out, an encode writes
+/// what its bitrates predict for the duration and a remux writes the
+/// input's size. Quality is measured as [`FakeBackend::quality`], a
+/// sample's VMAF is [`FakeBackend::sample_vmaf`] of its CRF, and samples
+/// come out at [`FakeBackend::sample_bits_per_pixel`].
+#[derive(Debug)]
+pub struct FakeBackend {
+    media: MediaInfo,
+    encoders: Vec<String>,
+    quality: QualityScores,
+    sample_vmaf: fn(u32) -> f64,
+    sample_bits_per_pixel: f64,
+    remux_fails: bool,
+    sizes: Mutex<VecDeque<u64>>,
+    calls: Mutex<Vec<FakeCall>>,
+}
+
+impl FakeBackend {
+    /// A backend probing every input as `media`, with every encoder
+    /// available.
+    pub fn new(media: MediaInfo) -> Self {
+        FakeBackend {
+            media,
+            encoders: FAKE_ENCODERS.iter().map(|e| e.to_string()).collect(),
+            quality: QualityScores::default(),
+            sample_vmaf: |crf| 120.0 - crf as f64,
+            sample_bits_per_pixel: 0.024,
+            remux_fails: false,
+            sizes: Mutex::new(VecDeque::new()),
+            calls: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Replace the encoders reported as available.
+    pub fn encoders(mut self, names: &[&str]) -> Self {
+        self.encoders = names.iter().map(|e| e.to_string()).collect();
+        self
+    }
+
+    /// Scores every output measures as.
+    pub fn quality(mut self, scores: QualityScores) -> Self {
+        self.quality = scores;
+        self
+    }
+
+    /// VMAF of a sample encoded at a given CRF; by default 100 at CRF 20
+    /// and a point lower for every step above.
+    pub fn sample_vmaf(mut self, vmaf: fn(u32) -> f64) -> Self {
+        self.sample_vmaf = vmaf;
+        self
+    }
+
+    /// Bits per pixel per frame of every sample's video; by default what
+    /// typical content needs in H.264 at its default CRF.
+    pub fn sample_bits_per_pixel(mut self, bits: f64) -> Self {
+        self.sample_bits_per_pixel = bits;
+        self
+    }
+
+    /// Make every remux leave an empty output and fail, as ffmpeg does
+    /// when it cannot fit the streams into the output container.
+    pub fn remux_fails(mut self, fails: bool) -> Self {
+        self.remux_fails = fails;
+        self
+    }
+
+    /// Sizes in bytes of the files written, in call order.
+    pub fn output_sizes(self, sizes: impl IntoIterator<Item = u64>) -> Self {
+        self.sizes.lock().unwrap().extend(sizes);
+        self
+    }
+
+    /// Every call so far, in order.
+    pub fn calls(&self) -> Vec<FakeCall> {
+        self.calls.lock().unwrap().clone()
+    }
+
+    fn write(&self, path: &Path, call: FakeCall, default_bytes: u64) -> Result<()> {
+        self.calls.lock().unwrap().push(call);
+        let bytes = self.sizes.lock().unwrap().pop_front();
+        File::create(path)?.set_len(bytes.unwrap_or(default_bytes))?;
+        Ok(())
+    }
+}
+
+impl Prober for FakeBackend {
+    fn probe(&self, _path: &Path) -> Result<MediaInfo> {
+        Ok(self.media.clone())
+    }
+}
+
+impl Encoder for FakeBackend {
+    fn encoders(&self) -> Result<Vec<String>> {
+        Ok(self.encoders.clone())
+    }
+
+    fn encode(&self, plan: &EncodePlan) -> Result<()> {
+        let bitrate = plan.video_bitrate + plan.audio.map_or(0, |a| a.bitrate);
+        let predicted = (bitrate as f64 / 8.0 * self.media.duration.unwrap_or(0.0)) as u64;
+        let call = FakeCall::Encode {
+            video_bitrate: plan.video_bitrate,
+        };
+        self.write(plan.job.output(), call, predicted)
+    }
+
+    fn remux(&self, job: &ShrinkJob, _media: &MediaInfo, _streams: &StreamSelection) -> Result<()> {
+        if self.remux_fails {
+            self.calls.lock().unwrap().push(FakeCall::Remux);
+            File::create(job.output())?;
+            return Err(Error::Encoder {
+                code: Some(1),
+                stderr_tail: "remux failed".to_string(),
+            });
+        }
+        let input_bytes = std::fs::metadata(job.input())?.len();
+        self.write(job.output(), FakeCall::Remux, input_bytes)
+    }
+
+    fn measure_quality(
+        &self,
+        _job: &ShrinkJob,
+        _input: &MediaInfo,
+        _output: &MediaInfo,
+    ) -> Result<QualityScores> {
+        self.calls.lock().unwrap().push(FakeCall::MeasureQuality);
+        Ok(self.quality)
+    }
+
+    fn sample_quality(&self, plan: &EncodePlan, sample: Sample) -> Result<QualityScores> {
+        let crf = match plan.rate_control {
+            RateControl::Quality { crf: Some(crf) } => crf,
+            mode => panic!("samples are encoded at a fixed CRF, not {:?}", mode),
+        };
+        self.calls
+            .lock()
+            .unwrap()
+            .push(FakeCall::SampleQuality { crf, sample });
+        Ok(QualityScores {
+            vmaf: Some((self.sample_vmaf)(crf)),
+            ..QualityScores::default()
+        })
+    }
+
+    fn sample_size(&self, plan: &EncodePlan, sample: Sample) -> Result<u64> {
+        self.calls
+            .lock()
+            .unwrap()
+            .push(FakeCall::SampleSize { sample });
+        let video = self.media.video();
+        let (w, h) = plan
+            .size
+            .or_else(|| video.and_then(|v| v.display_size()))
+            .unwrap_or((0, 0));
+        let fps = plan.fps.or_else(|| video.and_then(|v| v.frame_rate()));
+        let seconds = sample.duration.or(self.media.duration).unwrap_or(0.0);
+        let frames = seconds * fps.unwrap_or(0.0);
+        let pixels = w as f64 * h as f64;
+        Ok((self.sample_bits_per_pixel * pixels * frames / 8.0) as u64)
+    }
+}
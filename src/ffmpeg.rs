@@ -0,0 +1,552 @@
+//! ffmpeg/ffprobe invocations.
+
+use std::collections::VecDeque;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{self, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use crate::audio::AudioSettings;
+use crate::codec::{Preset, VideoCodec};
+use crate::command::{Command, Filter, Input, Output, StreamType};
+use crate::container::Container;
+use crate::error::{Error, Result};
+use crate::probe::MediaInfo;
+use crate::progress::{ProgressParser, Reporter};
+use crate::quality::{self, QualityScores};
+use crate::ratecontrol::RateControl;
+use crate::sample::Sample;
+use crate::streams::StreamSelection;
+use crate::ShrinkJob;
+
+/// Lines of ffmpeg stderr kept for [`Error::Encoder`].
+const STDERR_TAIL_LINES: usize = 20;
+
+/// Everything decided for one encode of a job.
+#[derive(Debug, Clone, Copy)]
+pub struct EncodePlan<'a> {
+    pub job: &'a ShrinkJob,
+    pub media: &'a MediaInfo,
+    pub streams: &'a StreamSelection,
+    /// The job's rate control, with any CRF search already settled.
+    pub rate_control: RateControl,
+    pub preset: Preset,
+    /// `None` drops audio, e.g. when the input has none.
+    pub audio: Option<AudioSettings>,
+    /// Average bitrate, or the cap in capped-quality mode (bps).
+    pub video_bitrate: u64,
+    /// Output (width, height); `None` keeps the source size.
+    pub size: Option<(u32, u32)>,
+    /// Output frame rate; `None` keeps the source timing.
+    pub fps: Option<f64>,
+}
+
+/// Encode once with the job's rate control, in one pass if the codec
+/// cannot do two.
+pub(crate) fn encode(plan: &EncodePlan) -> Result<()> {
+    if uses_two_pass(plan) {
+        encode_two_pass(plan)
+    } else {
+        run_ffmpeg(
+            &single_pass_command(plan),
+            reporter(plan.job, plan.media.duration, "encode"),
+        )
+    }
+}
+
+/// The commands [`encode`] runs, in order. Two-pass encodes keep their
+/// stats under `passlog`.
+pub(crate) fn encode_commands(plan: &EncodePlan, passlog: &Path) -> Vec<Command> {
+    if uses_two_pass(plan) {
+        Vec::from(two_pass_commands(plan, passlog))
+    } else {
+        vec![single_pass_command(plan)]
+    }
+}
+
+pub(crate) fn uses_two_pass(plan: &EncodePlan) -> bool {
+    plan.rate_control.two_pass() && plan.job.options().codec.supports_two_pass()
+}
+
+/// [`input_command`] reading the whole of `job`'s input.
+fn base_command(job: &ShrinkJob) -> Command {
+    input_command(Input::new(job.input()))
+}
+
+/// Global flags and the input. ffmpeg logs only errors to stderr and
+/// writes machine-readable progress to stdout.
+fn input_command(input: Input) -> Command {
+    Command::ffmpeg()
+        .flag("y")
+        .flag("hide_banner")
+        .flag("nostdin")
+        .option("loglevel", "error")
+        .flag("nostats")
+        .option("progress", "pipe:1")
+        .input(input)
+}
+
+/// The video stream with the encoder settings shared by every pass, plus
+/// the frame-rate and downscale filters.
+fn video_output(plan: &EncodePlan, output: Output) -> Output {
+    let job = plan.job;
+    let opts = job.options();
+    let codec = opts.codec;
+    let mut output = plan
+        .streams
+        .map_video(output)
+        .codec(StreamType::Video, codec.encoder())
+        .stream_options(StreamType::Video, codec.preset_options(plan.preset))
+        .stream_options(
+            StreamType::Video,
+            plan.rate_control.options(codec, plan.video_bitrate),
+        );
+    if job.container().is_isobmff() {
+        output = output.stream_options(StreamType::Video, codec.tag_options());
+    }
+    if let Some(threads) = opts.threads {
+        output = output.stream_option(StreamType::Video, "threads", threads.to_string());
+    }
+    // Drop frames before scaling so the scaler has less to do. ffmpeg
+    // autorotates before filtering, so the size is in display terms.
+    if let Some(fps) = plan.fps {
+        output = output.video_filter(Filter::new("fps").arg(fps));
+    }
+    if let Some((w, h)) = plan.size {
+        output = output.video_filter(Filter::new("scale").arg(w).arg(h));
+    }
+    output
+}
+
+/// [`input_command`] for an encode of `plan`. Without the fps filter,
+/// variable-frame-rate timestamps are kept as they are instead of being
+/// padded out to a constant rate. `-vsync` rather than its replacement
+/// `-fps_mode`, which ffmpeg 4.x does not know.
+fn encode_command(plan: &EncodePlan, input: Input) -> Command {
+    let command = input_command(input);
+    let vfr = plan
+        .media
+        .video()
+        .is_some_and(|v| v.is_variable_frame_rate());
+    if plan.fps.is_none() && vfr {
+        command.option("vsync", "vfr")
+    } else {
+        command
+    }
+}
+
+/// Audio, subtitles and muxer settings for the final output.
+fn finish_output(plan: &EncodePlan, output: Output) -> Output {
+    let container = plan.job.container();
+    let output = plan.streams.map_others(output, container);
+    let output = match &plan.audio {
+        Some(audio) => output.stream_options(StreamType::Audio, audio.options()),
+        None => output.disable(StreamType::Audio),
+    };
+    muxer(output, container)
+}
+
+fn muxer(output: Output, container: Container) -> Output {
+    output
+        .format(container.muxer())
+        .muxer_options(container.muxer_options())
+}
+
+/// Progress for `stage` of `job` over `duration` seconds of input.
+/// Labels name the file, since batch jobs may run side by side.
+fn reporter(job: &ShrinkJob, duration: Option<f64>, stage: &str) -> Reporter {
+    let name = job
+        .input()
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy();
+    let label = format!("{} {}", name, stage);
+    Reporter::new(job.options().progress, &label, duration)
+}
+
+fn single_pass_command(plan: &EncodePlan) -> Command {
+    let output = video_output(plan, Output::new(plan.job.output()));
+    encode_command(plan, Input::new(plan.job.input())).output(finish_output(plan, output))
+}
+
+/// Rewrap the input's kept streams into the output container without
+/// re-encoding audio or video.
+pub(crate) fn remux(job: &ShrinkJob, media: &MediaInfo, streams: &StreamSelection) -> Result<()> {
+    run_ffmpeg(
+        &remux_command(job, streams),
+        reporter(job, media.duration, "remux"),
+    )
+}
+
+pub(crate) fn remux_command(job: &ShrinkJob, streams: &StreamSelection) -> Command {
+    let container = job.container();
+    let output = streams
+        .map_video(Output::new(job.output()))
+        .codec(StreamType::Video, "copy")
+        .codec(StreamType::Audio, "copy");
+    let output = streams.map_others(output, container);
+    base_command(job).output(muxer(output, container))
+}
+
+/// Score `job`'s output against its input with the `libvmaf` (if this
+/// ffmpeg has it), `ssim` and `psnr` filters.
+pub(crate) fn measure_quality(
+    job: &ShrinkJob,
+    input: &MediaInfo,
+    output: &MediaInfo,
+) -> Result<QualityScores> {
+    let vmaf = filters()?.iter().any(|f| f == "libvmaf");
+    if !vmaf {
+        eprintln!("ffmpeg has no libvmaf; measuring SSIM and PSNR only");
+    }
+    let metrics: Vec<&str> = [vmaf.then_some("libvmaf"), Some("ssim"), Some("psnr")]
+        .into_iter()
+        .flatten()
+        .collect();
+    let fps = |media: &MediaInfo| media.video().and_then(|v| v.frame_rate());
+    let out_fps = fps(output).filter(|out| fps(input).is_some_and(|i| (out - i).abs() > 0.01));
+    let size = output.video().and_then(|v| v.display_size());
+    run_quality(&quality_command(
+        Input::new(job.output()),
+        Input::new(job.input()),
+        size,
+        out_fps,
+        &metrics,
+    ))
+}
+
+/// Encode `sample` of the input as `plan` describes, without audio, and
+/// score it against the same stretch of the input with VMAF.
+pub(crate) fn sample_quality(plan: &EncodePlan, sample: Sample) -> Result<QualityScores> {
+    if !filters()?.iter().any(|f| f == "libvmaf") {
+        return Err(Error::MissingFilter { name: "libvmaf" });
+    }
+    with_sample(plan, sample, |path| {
+        run_quality(&quality_command(
+            Input::new(path),
+            sample_input(plan.job.input(), sample),
+            plan.size,
+            plan.fps,
+            &["libvmaf"],
+        ))
+    })
+}
+
+/// Encode `sample` of the input as `plan` describes, without audio, and
+/// return its size in bytes.
+pub(crate) fn sample_size(plan: &EncodePlan, sample: Sample) -> Result<u64> {
+    with_sample(plan, sample, |path| Ok(std::fs::metadata(path)?.len()))
+}
+
+/// Encode `sample` into a temp file, hand its path to `inspect`, then
+/// remove it.
+fn with_sample<T>(
+    plan: &EncodePlan,
+    sample: Sample,
+    inspect: impl FnOnce(&Path) -> Result<T>,
+) -> Result<T> {
+    let dir = temp_dir();
+    std::fs::create_dir_all(&dir)?;
+    let container = plan.job.container();
+    let path = dir.join(format!("sample.{}", container.extension()));
+
+    let output = video_output(plan, Output::new(&path))
+        .disable(StreamType::Audio)
+        .disable(StreamType::Subtitle);
+    let encode = encode_command(plan, sample_input(plan.job.input(), sample))
+        .output(muxer(output, container));
+    let label = format!("sample at {:.0}s", sample.start);
+    let duration = sample.duration.or(plan.media.duration);
+    let result =
+        run_ffmpeg(&encode, reporter(plan.job, duration, &label)).and_then(|()| inspect(&path));
+    if let Err(e) = std::fs::remove_dir_all(&dir) {
+        eprintln!("could not remove {}: {}", dir.display(), e);
+    }
+    result
+}
+
+/// `path` read from `sample.start` for `sample.duration` seconds.
+fn sample_input(path: &Path, sample: Sample) -> Input {
+    let mut input = Input::new(path);
+    if sample.start > 0.0 {
+        input = input.option("ss", format!("{:.3}", sample.start));
+    }
+    if let Some(duration) = sample.duration {
+        input = input.option("t", format!("{:.3}", duration));
+    }
+    input
+}
+
+fn run_quality(command: &Command) -> Result<QualityScores> {
+    let out = process::Command::new(command.program())
+        .args(command.args())
+        .output()
+        .map_err(|e| spawn_error("ffmpeg", e))?;
+    if !out.status.success() {
+        let mut tail = StderrTail::default();
+        tail.push(&out.stderr);
+        return Err(Error::Encoder {
+            code: out.status.code(),
+            stderr_tail: tail.into_string(),
+        });
+    }
+    Ok(quality::parse(&String::from_utf8_lossy(&out.stderr)))
+}
+
+/// Decode `distorted` and `reference` side by side and feed both to every
+/// metric. The reference is brought to the distorted frame rate (`fps`)
+/// and display size (`size`) first, when those differ, and both start at
+/// zero so frames pair up. The filters log their summaries at info level.
+fn quality_command(
+    distorted: Input,
+    reference: Input,
+    size: Option<(u32, u32)>,
+    fps: Option<f64>,
+    metrics: &[&str],
+) -> Command {
+    let common = [
+        Filter::new("format").arg("yuv420p"),
+        Filter::new("setpts").arg("PTS-STARTPTS"),
+        Filter::new("split").arg(metrics.len()),
+    ];
+    let mut scaled = Vec::new();
+    if let Some(fps) = fps {
+        scaled.push(Filter::new("fps").arg(fps));
+    }
+    if let Some((w, h)) = size {
+        scaled.push(Filter::new("scale").arg(w).arg(h).arg("flags=bicubic"));
+    }
+    scaled.extend(common.clone());
+
+    let chain = |filters: &[Filter]| {
+        filters
+            .iter()
+            .map(Filter::to_string)
+            .collect::<Vec<_>>()
+            .join(",")
+    };
+    let labels = |prefix: char| {
+        (0..metrics.len())
+            .map(|i| format!("[{}{}]", prefix, i))
+            .collect::<String>()
+    };
+    let mut graph = vec![
+        format!("[0:v]{}{}", chain(&common), labels('d')),
+        format!("[1:v]{}{}", chain(&scaled), labels('r')),
+    ];
+    graph.extend(
+        metrics
+            .iter()
+            .enumerate()
+            .map(|(i, metric)| format!("[d{}][r{}]{}", i, i, metric)),
+    );
+
+    Command::ffmpeg()
+        .flag("hide_banner")
+        .flag("nostdin")
+        .option("loglevel", "info")
+        .flag("nostats")
+        .input(distorted)
+        .input(reference)
+        .option("lavfi", graph.join(";"))
+        .output(
+            Output::new("-")
+                .disable(StreamType::Audio)
+                .disable(StreamType::Subtitle)
+                .format("null"),
+        )
+}
+
+/// Pass 1 analyses into a null muxer, pass 2 encodes from its stats.
+/// The passlog files live in a private temp dir removed afterwards.
+fn encode_two_pass(plan: &EncodePlan) -> Result<()> {
+    let log_dir = temp_dir();
+    std::fs::create_dir_all(&log_dir)?;
+    let [first, second] = two_pass_commands(plan, &log_dir.join("ffmpeg2pass"));
+    let result = run_ffmpeg(&first, reporter(plan.job, plan.media.duration, "pass 1/2"))
+        .and_then(|()| run_ffmpeg(&second, reporter(plan.job, plan.media.duration, "pass 2/2")));
+    if let Err(e) = std::fs::remove_dir_all(&log_dir) {
+        eprintln!("could not remove {}: {}", log_dir.display(), e);
+    }
+    result
+}
+
+/// libx265 finds its stats by file name alone (see
+/// [`VideoCodec::pass_options`]), so its passes run in the passlog's
+/// directory with the input and output given as absolute paths.
+fn two_pass_commands(plan: &EncodePlan, passlog: &Path) -> [Command; 2] {
+    let null_sink = if cfg!(windows) { "NUL" } else { "/dev/null" };
+    let codec = plan.job.options().codec;
+    let log_dir = passlog.parent().filter(|_| codec == VideoCodec::H265);
+    let path = |path: &Path| match log_dir {
+        Some(_) => std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf()),
+        None => path.to_path_buf(),
+    };
+    let command = || {
+        let command = encode_command(plan, Input::new(path(plan.job.input())));
+        match log_dir {
+            Some(dir) => command.current_dir(dir),
+            None => command,
+        }
+    };
+
+    let first = video_output(plan, Output::new(null_sink))
+        .stream_options(StreamType::Video, codec.pass_options(1, passlog))
+        .disable(StreamType::Audio)
+        .format("null");
+
+    let second = video_output(plan, Output::new(path(plan.job.output())))
+        .stream_options(StreamType::Video, codec.pass_options(2, passlog));
+    [
+        command().output(first),
+        command().output(finish_output(plan, second)),
+    ]
+}
+
+/// Where printed two-pass commands keep their stats: one fixed
+/// directory, since a real run's [`temp_dir`] is not known in advance.
+pub(crate) fn printed_passlog_dir() -> PathBuf {
+    std::env::temp_dir().join("mp4_shrink-passlog")
+}
+
+/// Unique directory for passlog files and samples, so concurrent jobs
+/// never share one.
+pub(crate) fn temp_dir() -> PathBuf {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let n = NEXT.fetch_add(1, Ordering::Relaxed);
+    std::env::temp_dir().join(format!("mp4_shrink-{}-{}", std::process::id(), n))
+}
+
+/// Run ffmpeg with the given arguments, feeding its progress to
+/// `reporter`, echoing its stderr and keeping the last lines for the
+/// error report.
+fn run_ffmpeg(command: &Command, mut reporter: Reporter) -> Result<()> {
+    let mut child = process::Command::new(command.program());
+    if let Some(dir) = command.dir() {
+        child.current_dir(dir);
+    }
+    let mut child = child
+        .args(command.args())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| spawn_error("ffmpeg", e))?;
+
+    // Drain stderr on its own thread so neither pipe can fill up and block.
+    let mut stderr = child.stderr.take().expect("stderr is piped");
+    let stderr_reader = thread::spawn(move || {
+        let mut tail = StderrTail::default();
+        let mut buf = [0u8; 4096];
+        while let Ok(n) = stderr.read(&mut buf) {
+            if n == 0 {
+                break;
+            }
+            // Echoing is best effort; a closed terminal must not abort the encode.
+            let _ = io::stderr().write_all(&buf[..n]);
+            tail.push(&buf[..n]);
+        }
+        tail
+    });
+
+    let stdout = child.stdout.take().expect("stdout is piped");
+    let mut parser = ProgressParser::default();
+    for line in BufReader::new(stdout).lines() {
+        if let Some(progress) = parser.line(&line?) {
+            reporter.update(&progress);
+        }
+    }
+    reporter.finish();
+
+    let status = child.wait()?;
+    let tail = stderr_reader.join().unwrap_or_default();
+
+    if !status.success() {
+        return Err(Error::Encoder {
+            code: status.code(),
+            stderr_tail: tail.into_string(),
+        });
+    }
+    Ok(())
+}
+
+/// Rolling window over the last [`STDERR_TAIL_LINES`] lines of output.
+/// ffmpeg rewrites its stats line with `\r`, so both `\r` and `\n` end a line.
+#[derive(Default)]
+struct StderrTail {
+    lines: VecDeque<String>,
+    partial: Vec<u8>,
+}
+
+impl StderrTail {
+    fn push(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            if b == b'\n' || b == b'\r' {
+                self.end_line();
+            } else {
+                self.partial.push(b);
+            }
+        }
+    }
+
+    fn end_line(&mut self) {
+        if self.partial.is_empty() {
+            return;
+        }
+        let line = String::from_utf8_lossy(&self.partial).into_owned();
+        self.partial.clear();
+        if self.lines.len() == STDERR_TAIL_LINES {
+            self.lines.pop_front();
+        }
+        self.lines.push_back(line);
+    }
+
+    fn into_string(mut self) -> String {
+        self.end_line();
+        Vec::from(self.lines).join("\n")
+    }
+}
+
+/// Names of every encoder in the local ffmpeg build.
+pub(crate) fn encoders() -> Result<Vec<String>> {
+    // Lines look like ` V....D libx264   libx264 H.264 / AVC ...`.
+    listing("encoders")
+}
+
+/// Names of every filter in the local ffmpeg build.
+fn filters() -> Result<Vec<String>> {
+    // Lines look like ` ... libvmaf   VV->V   Calculate the VMAF ...`.
+    listing("filters")
+}
+
+/// The second column of `ffmpeg -<flag>`, which names each entry.
+fn listing(flag: &'static str) -> Result<Vec<String>> {
+    let command = Command::ffmpeg().flag("hide_banner").flag(flag);
+    let out = process::Command::new(command.program())
+        .args(command.args())
+        .output()
+        .map_err(|e| spawn_error("ffmpeg", e))?;
+    Ok(String::from_utf8_lossy(&out.stdout)
+        .lines()
+        .filter_map(|line| line.split_whitespace().nth(1))
+        .map(str::to_string)
+        .collect())
+}
+
+/// Fail with [`Error::MissingEncoder`] unless `encoders` lists `name`.
+pub(crate) fn require_encoder(encoders: &[String], name: &'static str) -> Result<()> {
+    if encoders.iter().any(|e| e == name) {
+        Ok(())
+    } else {
+        Err(Error::MissingEncoder { name })
+    }
+}
+
+/// Map a failed spawn to [`Error::MissingBinary`] when the program is absent.
+pub(crate) fn spawn_error(name: &'static str, e: io::Error) -> Error {
+    if e.kind() == io::ErrorKind::NotFound {
+        Error::MissingBinary { name }
+    } else {
+        Error::Io(e)
+    }
+}
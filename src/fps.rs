@@ -0,0 +1,79 @@
+//! Choosing the output frame rate.
+
+/// Rates the automatic mode steps down through, fastest first.
+const STEPS: &[f64] = &[30.0, 24.0, 15.0];
+
+/// Limits on the output frame rate.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FpsPolicy {
+    /// Never output more frames per second than this.
+    pub max_fps: Option<f64>,
+    /// Step down to 30, 24, then 15 fps while the bitrate leaves each
+    /// frame with less than half the codec's bits-per-pixel budget (less
+    /// still for hard, high-motion content).
+    pub auto: bool,
+}
+
+impl Default for FpsPolicy {
+    fn default() -> Self {
+        FpsPolicy {
+            max_fps: None,
+            auto: true,
+        }
+    }
+}
+
+impl FpsPolicy {
+    pub fn max_fps(mut self, fps: Option<f64>) -> Self {
+        self.max_fps = fps;
+        self
+    }
+
+    pub fn auto(mut self, enabled: bool) -> Self {
+        self.auto = enabled;
+        self
+    }
+
+    /// Output frame rate for a source averaging `source_fps`, or `None` to
+    /// keep the source timing. `pixels` is the frame area we would like to
+    /// keep. Resolution absorbs moderate shortages; frames are only dropped
+    /// when the budget is badly short, since motion suffers more than detail.
+    ///
+    /// `source_fps` must be the *average* rate: variable-frame-rate sources
+    /// often report a base rate far above what they actually deliver, and
+    /// capping against that would pad the output with duplicated frames.
+    ///
+    /// `bits_per_pixel` is the per-pixel budget, normally the codec's;
+    /// frames are dropped below half of it.
+    pub fn target_fps(
+        &self,
+        source_fps: f64,
+        pixels: u64,
+        video_bitrate: u64,
+        bits_per_pixel: f64,
+    ) -> Option<f64> {
+        if source_fps <= 0.0 {
+            return None;
+        }
+        let mut fps = source_fps;
+        if let Some(max) = self.max_fps.filter(|m| *m > 0.0) {
+            fps = fps.min(max);
+        }
+        if self.auto && pixels > 0 {
+            let floor = bits_per_pixel / 2.0;
+            let bpp = |fps: f64| video_bitrate as f64 / (pixels as f64 * fps);
+            for &step in STEPS {
+                if bpp(fps) >= floor {
+                    break;
+                }
+                fps = fps.min(step);
+            }
+        }
+        // Ignore rounding noise such as 29.97 against a 30 fps cap.
+        if fps < source_fps * 0.99 {
+            Some(fps)
+        } else {
+            None
+        }
+    }
+}
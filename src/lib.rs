@@ -0,0 +1,1041 @@
+//! Shrink videos to a target size by re-encoding with ffmpeg.
+//!
+//! Build a [`ShrinkJob`] from an input, an output and [`ShrinkOptions`],
+//! then call [`ShrinkJob::run`] to get a [`ShrinkReport`].
+//!
+//! Jobs probe through a [`Prober`] and encode through an [`Encoder`],
+//! both [`Ffmpeg`] unless replaced. With the `test-support` feature,
+//! `FakeBackend` stands in for ffmpeg in tests.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
+
+mod audio;
+mod backend;
+pub mod batch;
+mod budget;
+mod codec;
+mod command;
+mod complexity;
+mod container;
+mod error;
+mod estimate;
+#[cfg(feature = "test-support")]
+mod fake;
+mod ffmpeg;
+mod fps;
+mod passthrough;
+pub mod probe;
+mod progress;
+mod quality;
+mod ratecontrol;
+mod sample;
+mod scale;
+pub mod scheduler;
+mod streams;
+
+pub use audio::{AudioCodec, AudioSettings};
+pub use backend::{Encoder, Ffmpeg, Prober};
+pub use budget::Budget;
+pub use codec::{Preset, VideoCodec};
+pub use complexity::Complexity;
+pub use container::Container;
+pub use error::{Error, Result};
+pub use estimate::{Estimate, Verdict};
+#[cfg(feature = "test-support")]
+pub use fake::{FakeBackend, FakeCall};
+pub use ffmpeg::EncodePlan;
+pub use fps::FpsPolicy;
+pub use passthrough::Passthrough;
+pub use probe::{MediaInfo, StreamInfo, StreamKind};
+pub use progress::ProgressStyle;
+pub use quality::{QualityFloor, QualityScores};
+pub use ratecontrol::RateControl;
+pub use sample::Sample;
+pub use scale::ScalePolicy;
+pub use streams::StreamSelection;
+
+/// Default bounds for the auto-calculated video bitrate (bps).
+const DEFAULT_BITRATE_FLOOR: u64 = 200_000;
+const DEFAULT_BITRATE_CEILING: u64 = 1_500_000;
+
+/// Video bitrate used when none is given and the duration is unknown.
+const FALLBACK_VIDEO_BITRATE: u64 = 500_000;
+
+/// Encoding settings for a [`ShrinkJob`].
+#[derive(Debug, Clone)]
+pub struct ShrinkOptions {
+    /// Target file size in bytes.
+    pub target_bytes: u64,
+    /// Fixed video bitrate (bps) for [`RateControl::TargetSize`];
+    /// auto-calculated from the target if `None`.
+    pub video_bitrate: Option<u64>,
+    /// Audio bitrate (bps).
+    pub audio_bitrate: u64,
+    /// Audio codec; [`AudioCodec::Auto`] picks one per container and bitrate.
+    pub audio_codec: AudioCodec,
+    /// Video codec and encoder.
+    pub codec: VideoCodec,
+    /// Output format; inferred from the output extension if `None`,
+    /// falling back to MP4.
+    pub container: Option<Container>,
+    /// How far the output resolution may and should be reduced.
+    pub scale: ScalePolicy,
+    /// How far the output frame rate may and should be reduced.
+    pub fps: FpsPolicy,
+    /// How the encoder trades size against quality.
+    pub rate_control: RateControl,
+    /// What to do with an input already within `target_bytes` instead of
+    /// re-encoding it; `None` encodes it anyway.
+    pub under_target: Option<Passthrough>,
+    /// Lowest auto-calculated video bitrate (bps), however small the target.
+    pub bitrate_floor: u64,
+    /// Highest auto-calculated video bitrate (bps), however large the target.
+    pub bitrate_ceiling: u64,
+    /// Fail before encoding when the floor makes the target unreachable,
+    /// instead of warning and encoding anyway.
+    pub strict: bool,
+    /// Maximum number of encodes while converging on the target size.
+    pub max_attempts: u32,
+    /// Accept outputs this fraction under the target without re-encoding.
+    pub tolerance: f64,
+    /// How encode progress is shown on stderr.
+    pub progress: ProgressStyle,
+    /// ffmpeg `-threads` budget; `None` lets the encoder decide.
+    pub threads: Option<u32>,
+    /// Score an encoded output against its input once it is final.
+    pub quality_report: bool,
+    /// Scores below which the quality report warns.
+    pub quality_floor: QualityFloor,
+    /// Encode samples of the input first to measure how hard it is to
+    /// compress, and let that steer the resolution, frame rate and preset.
+    pub analyze: bool,
+}
+
+impl Default for ShrinkOptions {
+    fn default() -> Self {
+        ShrinkOptions {
+            target_bytes: 10 * 1024 * 1024,
+            video_bitrate: None,
+            audio_bitrate: 64_000,
+            audio_codec: AudioCodec::Auto,
+            codec: VideoCodec::H264,
+            container: None,
+            scale: ScalePolicy::default(),
+            fps: FpsPolicy::default(),
+            rate_control: RateControl::default(),
+            under_target: Some(Passthrough::Remux),
+            bitrate_floor: DEFAULT_BITRATE_FLOOR,
+            bitrate_ceiling: DEFAULT_BITRATE_CEILING,
+            strict: false,
+            max_attempts: 3,
+            tolerance: 0.1,
+            progress: ProgressStyle::Off,
+            threads: None,
+            quality_report: false,
+            quality_floor: QualityFloor::default(),
+            analyze: false,
+        }
+    }
+}
+
+impl ShrinkOptions {
+    pub fn target_bytes(mut self, bytes: u64) -> Self {
+        self.target_bytes = bytes;
+        self
+    }
+
+    pub fn video_bitrate(mut self, bps: Option<u64>) -> Self {
+        self.video_bitrate = bps;
+        self
+    }
+
+    pub fn audio_bitrate(mut self, bps: u64) -> Self {
+        self.audio_bitrate = bps;
+        self
+    }
+
+    pub fn audio_codec(mut self, codec: AudioCodec) -> Self {
+        self.audio_codec = codec;
+        self
+    }
+
+    pub fn codec(mut self, codec: VideoCodec) -> Self {
+        self.codec = codec;
+        self
+    }
+
+    pub fn container(mut self, container: Option<Container>) -> Self {
+        self.container = container;
+        self
+    }
+
+    pub fn scale(mut self, policy: ScalePolicy) -> Self {
+        self.scale = policy;
+        self
+    }
+
+    pub fn fps(mut self, policy: FpsPolicy) -> Self {
+        self.fps = policy;
+        self
+    }
+
+    pub fn rate_control(mut self, mode: RateControl) -> Self {
+        self.rate_control = mode;
+        self
+    }
+
+    pub fn under_target(mut self, mode: Option<Passthrough>) -> Self {
+        self.under_target = mode;
+        self
+    }
+
+    pub fn bitrate_floor(mut self, bps: u64) -> Self {
+        self.bitrate_floor = bps;
+        self
+    }
+
+    pub fn bitrate_ceiling(mut self, bps: u64) -> Self {
+        self.bitrate_ceiling = bps;
+        self
+    }
+
+    pub fn strict(mut self, enabled: bool) -> Self {
+        self.strict = enabled;
+        self
+    }
+
+    pub fn max_attempts(mut self, attempts: u32) -> Self {
+        self.max_attempts = attempts;
+        self
+    }
+
+    pub fn tolerance(mut self, fraction: f64) -> Self {
+        self.tolerance = fraction;
+        self
+    }
+
+    pub fn progress(mut self, style: ProgressStyle) -> Self {
+        self.progress = style;
+        self
+    }
+
+    pub fn threads(mut self, threads: Option<u32>) -> Self {
+        self.threads = threads;
+        self
+    }
+
+    pub fn quality_report(mut self, enabled: bool) -> Self {
+        self.quality_report = enabled;
+        self
+    }
+
+    pub fn quality_floor(mut self, floor: QualityFloor) -> Self {
+        self.quality_floor = floor;
+        self
+    }
+
+    pub fn analyze(mut self, enabled: bool) -> Self {
+        self.analyze = enabled;
+        self
+    }
+}
+
+/// One input/output pair to shrink with a set of options.
+#[derive(Debug, Clone)]
+pub struct ShrinkJob {
+    input: PathBuf,
+    output: PathBuf,
+    options: ShrinkOptions,
+    prober: Arc<dyn Prober>,
+    encoder: Arc<dyn Encoder>,
+}
+
+/// One encode of the convergence loop.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Attempt {
+    /// Video bitrate, or bitrate cap, used for this encode (bps); `None`
+    /// in constant-quality mode.
+    pub video_bitrate: Option<u64>,
+    /// Size of the produced file in bytes.
+    pub output_bytes: u64,
+}
+
+/// Outcome of [`ShrinkJob::run`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShrinkReport {
+    /// What ffprobe found in the input.
+    pub media: MediaInfo,
+    /// Size of the input file in bytes.
+    pub input_bytes: u64,
+    /// Size of the final output file in bytes.
+    pub output_bytes: u64,
+    /// Input streams kept in the output.
+    pub streams: StreamSelection,
+    /// How the target was split up; `None` if the duration is unknown.
+    pub budget: Option<Budget>,
+    /// Audio settings used for every attempt; `None` if the input has no audio.
+    pub audio: Option<AudioSettings>,
+    /// Output (width, height); `None` if the source size was kept.
+    pub video_size: Option<(u32, u32)>,
+    /// Output frame rate; `None` if the source timing was kept.
+    pub fps: Option<f64>,
+    /// The CRF the target-quality search settled on.
+    pub crf: Option<u32>,
+    /// What the analysis pass measured, with [`ShrinkOptions::analyze`].
+    pub complexity: Option<Complexity>,
+    /// Every encode in order; the last one produced the output unless
+    /// `passthrough` is set.
+    pub attempts: Vec<Attempt>,
+    /// Set when the output is the input written out unchanged, either
+    /// because it already fit or because encoding made it larger.
+    pub passthrough: Option<Passthrough>,
+    /// Scores of an encoded output against the input, with
+    /// [`ShrinkOptions::quality_report`]; `None` for a pass-through.
+    pub quality: Option<QualityScores>,
+}
+
+impl ShrinkReport {
+    /// Overall bitrate of the output (bps); `None` if the duration is unknown.
+    pub fn bitrate(&self) -> Option<u64> {
+        let duration = self.media.duration.filter(|d| *d > 0.0)?;
+        Some((self.output_bytes as f64 * 8.0 / duration) as u64)
+    }
+}
+
+/// What [`ShrinkJob::plan`] decided, before anything is written.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShrinkPlan {
+    pub media: MediaInfo,
+    /// Size of the input file in bytes.
+    pub input_bytes: u64,
+    /// Input streams to keep in the output.
+    pub streams: StreamSelection,
+    /// Set when the input already fits and is to be written out as is.
+    pub passthrough: Option<Passthrough>,
+    /// How to encode; `None` when `passthrough` is set.
+    pub encode: Option<EncodeSettings>,
+}
+
+/// Settings for the first encode of a job.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EncodeSettings {
+    /// How the target is split up; `None` if the duration is unknown.
+    pub budget: Option<Budget>,
+    /// `None` if the input has no audio.
+    pub audio: Option<AudioSettings>,
+    /// Video bitrate, or bitrate cap, of the first attempt (bps).
+    pub video_bitrate: u64,
+    /// Whether later attempts may move `video_bitrate`.
+    pub adjustable: bool,
+    /// Output (width, height); `None` keeps the source size.
+    pub video_size: Option<(u32, u32)>,
+    /// Output frame rate; `None` keeps the source timing.
+    pub fps: Option<f64>,
+    /// Encoder speed preset.
+    pub preset: Preset,
+    /// What the analysis pass measured, with [`ShrinkOptions::analyze`].
+    pub complexity: Option<Complexity>,
+}
+
+impl EncodeSettings {
+    fn encode_plan<'a>(&self, job: &'a ShrinkJob, plan: &'a ShrinkPlan) -> EncodePlan<'a> {
+        EncodePlan {
+            job,
+            media: &plan.media,
+            streams: &plan.streams,
+            rate_control: job.options.rate_control,
+            preset: self.preset,
+            audio: self.audio,
+            video_bitrate: self.video_bitrate,
+            size: self.video_size,
+            fps: self.fps,
+        }
+    }
+}
+
+impl ShrinkJob {
+    /// A job probed and encoded by [`Ffmpeg`].
+    pub fn new(
+        input: impl Into<PathBuf>,
+        output: impl Into<PathBuf>,
+        options: ShrinkOptions,
+    ) -> Self {
+        ShrinkJob {
+            input: input.into(),
+            output: output.into(),
+            options,
+            prober: Arc::new(Ffmpeg),
+            encoder: Arc::new(Ffmpeg),
+        }
+    }
+
+    /// Probe the input with `prober` instead of ffprobe.
+    pub fn prober(mut self, prober: Arc<dyn Prober>) -> Self {
+        self.prober = prober;
+        self
+    }
+
+    /// Encode and remux with `encoder` instead of ffmpeg.
+    pub fn encoder(mut self, encoder: Arc<dyn Encoder>) -> Self {
+        self.encoder = encoder;
+        self
+    }
+
+    pub fn input(&self) -> &Path {
+        &self.input
+    }
+
+    pub fn output(&self) -> &Path {
+        &self.output
+    }
+
+    pub fn options(&self) -> &ShrinkOptions {
+        &self.options
+    }
+
+    /// The output format: the configured one, else the output extension's.
+    pub fn container(&self) -> Container {
+        self.options
+            .container
+            .or_else(|| Container::from_path(&self.output))
+            .unwrap_or_default()
+    }
+
+    /// Check the options, probe the input and decide how to shrink it,
+    /// without writing the output. Settings are logged to stderr as they
+    /// are decided. With [`ShrinkOptions::analyze`] this encodes samples
+    /// of the input to a temp directory.
+    pub fn plan(&self) -> Result<ShrinkPlan> {
+        let opts = &self.options;
+        let input_bytes = std::fs::File::open(&self.input)
+            .and_then(|f| f.metadata())
+            .map_err(|source| Error::Input {
+                path: self.input.clone(),
+                source,
+            })?
+            .len();
+
+        // Writing the output would destroy the input.
+        let same_file = match (self.input.canonicalize(), self.output.canonicalize()) {
+            (Ok(input), Ok(output)) => input == output,
+            _ => false,
+        };
+        if same_file {
+            return Err(Error::InvalidArgument(format!(
+                "output {} is the input file",
+                self.output.display()
+            )));
+        }
+        if opts.bitrate_floor > opts.bitrate_ceiling {
+            return Err(Error::InvalidArgument(format!(
+                "bitrate floor {}bps is above the ceiling {}bps",
+                opts.bitrate_floor, opts.bitrate_ceiling
+            )));
+        }
+        let container = self.container();
+        if !container.supports(opts.codec) {
+            return Err(Error::InvalidArgument(format!(
+                "{} cannot be stored in {}",
+                opts.codec, container
+            )));
+        }
+        let encoders = self.encoder.encoders()?;
+        ffmpeg::require_encoder(&encoders, opts.codec.encoder())?;
+        if opts.rate_control.two_pass() && !opts.codec.supports_two_pass() {
+            eprintln!(
+                "warning: {} does not support two-pass; using one pass",
+                opts.codec
+            );
+        }
+        let media = self.prober.probe(&self.input)?;
+        let Some(streams) = StreamSelection::for_output(&media, container) else {
+            return Err(Error::Probe {
+                path: self.input.clone(),
+                message: "no video stream".to_string(),
+            });
+        };
+
+        // An input that already fits only needs writing out.
+        let fits = opts.rate_control.targets_size() && input_bytes <= opts.target_bytes;
+        let passthrough = opts.under_target.filter(|_| fits);
+        let encode = match passthrough {
+            Some(_) => {
+                eprintln!(
+                    "input is {} bytes, within the target; skipping the encode",
+                    input_bytes
+                );
+                None
+            }
+            None => Some(self.plan_encode(&media, &streams, &encoders)?),
+        };
+        Ok(ShrinkPlan {
+            media,
+            input_bytes,
+            streams,
+            passthrough,
+            encode,
+        })
+    }
+
+    /// The commands [`ShrinkJob::run`] would start with for `plan`, as
+    /// shell-escaped lines: the probe, then the pass-through or the first
+    /// encode attempt. A plain copy needs no command. In target-quality
+    /// mode a `#` comment says the CRF shown is not yet the searched one.
+    /// Two-pass stats go to `mp4_shrink-passlog` in the temp dir, created
+    /// by a `mkdir -p` line, so the lines run as printed.
+    pub fn command_lines(&self, plan: &ShrinkPlan) -> Vec<String> {
+        let mut lines = vec![probe::command(&self.input).shell_line()];
+        let commands = match (plan.passthrough, &plan.encode) {
+            (Some(mode), _) => match passthrough::resolve(self, mode) {
+                Passthrough::Copy => Vec::new(),
+                Passthrough::Remux => vec![ffmpeg::remux_command(self, &plan.streams)],
+            },
+            (None, Some(settings)) => {
+                if let RateControl::TargetQuality { vmaf, .. } = self.options.rate_control {
+                    lines.push(format!(
+                        "# crf chosen by sampling for VMAF >= {}; shown at the default",
+                        vmaf
+                    ));
+                }
+                let encode = settings.encode_plan(self, plan);
+                let log_dir = ffmpeg::printed_passlog_dir();
+                if ffmpeg::uses_two_pass(&encode) {
+                    lines.push(format!(
+                        "mkdir -p {}",
+                        command::shell_quote(&log_dir.to_string_lossy())
+                    ));
+                }
+                ffmpeg::encode_commands(&encode, &log_dir.join("ffmpeg2pass"))
+            }
+            (None, None) => Vec::new(),
+        };
+        lines.extend(commands.iter().map(|c| c.shell_line()));
+        lines
+    }
+
+    /// Probe the input, then encode until the output converges on the target.
+    /// An input already within the target is written out as configured by
+    /// [`ShrinkOptions::under_target`], and an encode that comes out larger
+    /// than the input is replaced by the original.
+    ///
+    /// In the size-targeting modes, fails with [`Error::TargetUnreachable`]
+    /// if the output is still over the target after the last attempt; that
+    /// output is left in place. Constant-quality mode encodes exactly once;
+    /// target-quality mode first searches for its CRF on samples of the
+    /// input.
+    pub fn run(&self) -> Result<ShrinkReport> {
+        let plan = self.plan()?;
+        let opts = &self.options;
+        let rate_control = opts.rate_control;
+        let input_bytes = plan.input_bytes;
+
+        if let Some(mode) = plan.passthrough {
+            match passthrough::pass_through(self, mode, &plan.media, &plan.streams) {
+                Ok(done) => {
+                    let output_bytes = std::fs::metadata(&self.output)?.len();
+                    if output_bytes <= opts.target_bytes {
+                        return Ok(ShrinkReport {
+                            media: plan.media,
+                            input_bytes,
+                            output_bytes,
+                            streams: plan.streams,
+                            budget: None,
+                            audio: None,
+                            video_size: None,
+                            fps: None,
+                            crf: None,
+                            complexity: None,
+                            attempts: Vec::new(),
+                            passthrough: Some(done),
+                            quality: None,
+                        });
+                    }
+                    eprintln!(
+                        "remuxed output is {} bytes, over the target; encoding instead",
+                        output_bytes
+                    );
+                }
+                Err(e @ Error::Encoder { .. }) => {
+                    eprintln!("warning: remux failed ({}); encoding instead", e);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        let settings = match plan.encode {
+            Some(settings) => settings,
+            None => self.plan_encode(&plan.media, &plan.streams, &self.encoder.encoders()?)?,
+        };
+
+        let crf = match rate_control {
+            RateControl::TargetQuality { vmaf, .. } => {
+                Some(self.search_crf(&plan, &settings, vmaf)?)
+            }
+            _ => None,
+        };
+        let rate_control = crf.map_or(rate_control, |crf| rate_control.with_crf(crf));
+
+        let mut v_bitrate = settings.video_bitrate;
+        let max_attempts = if settings.adjustable {
+            opts.max_attempts.max(1)
+        } else {
+            1
+        };
+        let mut attempts = Vec::new();
+        for attempt in 1..=max_attempts {
+            self.encoder.encode(&EncodePlan {
+                rate_control,
+                video_bitrate: v_bitrate,
+                ..settings.encode_plan(self, &plan)
+            })?;
+
+            let size = std::fs::metadata(&self.output)?.len();
+            attempts.push(Attempt {
+                video_bitrate: rate_control.limits_bitrate().then_some(v_bitrate),
+                output_bytes: size,
+            });
+            let error = size as f64 / opts.target_bytes as f64 - 1.0;
+            eprintln!(
+                "attempt {}/{}: {} -> {} bytes ({:+.1}% vs target)",
+                attempt,
+                max_attempts,
+                rate_summary(rate_control, opts.codec, v_bitrate),
+                size,
+                error * 100.0
+            );
+
+            // A capped CRF encode may come in well under the cap; only
+            // average-bitrate encodes are pulled up towards the target.
+            let converged = match rate_control {
+                RateControl::TargetSize { .. } => {
+                    size <= opts.target_bytes && error >= -opts.tolerance
+                }
+                _ => size <= opts.target_bytes,
+            };
+            if converged || attempt == max_attempts {
+                break;
+            }
+
+            let reserved = settings.budget.map_or(0, |b| b.reserved_bytes());
+            let next = corrected_bitrate(opts.target_bytes, reserved, v_bitrate, size)
+                .clamp(opts.bitrate_floor, opts.bitrate_ceiling);
+            if next == v_bitrate {
+                eprintln!("bitrate is at its limit; stopping");
+                break;
+            }
+            v_bitrate = next;
+        }
+
+        let mut output_bytes = attempts.last().map_or(0, |a| a.output_bytes);
+        let mut passthrough = None;
+        if output_bytes > input_bytes {
+            eprintln!(
+                "encoded output ({} bytes) is larger than the input ({} bytes); keeping the original",
+                output_bytes, input_bytes
+            );
+            match passthrough::keep_original(self, &plan.media, &plan.streams) {
+                Ok(done) => {
+                    passthrough = Some(done);
+                    output_bytes = std::fs::metadata(&self.output)?.len();
+                }
+                Err(e) => eprintln!(
+                    "warning: could not write out the original ({}); keeping the encode",
+                    e
+                ),
+            }
+        }
+        if rate_control.targets_size() && output_bytes > opts.target_bytes {
+            return Err(Error::TargetUnreachable {
+                target_bytes: opts.target_bytes,
+                output_bytes,
+            });
+        }
+        let quality = match passthrough {
+            None if opts.quality_report => self.quality_report(&plan.media),
+            _ => None,
+        };
+        Ok(ShrinkReport {
+            media: plan.media,
+            input_bytes,
+            output_bytes,
+            streams: plan.streams,
+            budget: settings.budget,
+            audio: settings.audio,
+            video_size: settings.video_size,
+            fps: settings.fps,
+            crf,
+            complexity: settings.complexity,
+            attempts,
+            passthrough,
+            quality,
+        })
+    }
+
+    /// Score the finished output and warn about every score under the
+    /// floor. A failed measurement is only a warning, since the output
+    /// itself is fine.
+    fn quality_report(&self, input: &MediaInfo) -> Option<QualityScores> {
+        let scores = self
+            .prober
+            .probe(&self.output)
+            .and_then(|output| self.encoder.measure_quality(self, input, &output));
+        match scores {
+            Ok(scores) => {
+                for breach in scores.breaches(&self.options.quality_floor) {
+                    eprintln!("warning: {}", breach);
+                }
+                Some(scores)
+            }
+            Err(e) => {
+                eprintln!("warning: could not measure quality: {}", e);
+                None
+            }
+        }
+    }
+
+    /// The highest CRF whose mean VMAF over samples of the input, encoded
+    /// as `settings` describe, reaches `vmaf`.
+    fn search_crf(&self, plan: &ShrinkPlan, settings: &EncodeSettings, vmaf: f64) -> Result<u32> {
+        let codec = self.options.codec;
+        let samples = sample::spread(plan.media.duration);
+        eprintln!(
+            "searching for the highest crf with VMAF >= {} on {} sample(s)",
+            vmaf,
+            samples.len()
+        );
+        let crf = quality::search_crf(codec.crf_range(), vmaf, |crf| {
+            let encode = EncodePlan {
+                rate_control: RateControl::Quality { crf: Some(crf) },
+                ..settings.encode_plan(self, plan)
+            };
+            let mut total = 0.0;
+            for sample in &samples {
+                let scores = self.encoder.sample_quality(&encode, *sample)?;
+                total += scores
+                    .vmaf
+                    .ok_or(Error::MissingFilter { name: "libvmaf" })?;
+            }
+            Ok(total / samples.len() as f64)
+        })?;
+        eprintln!("using crf {}", crf);
+        Ok(crf)
+    }
+
+    /// Predict the encode [`ShrinkJob::run`] would make by encoding
+    /// samples of the input at the planned size, frame rate and quality,
+    /// without writing the output. Size-targeting mode is sampled at the
+    /// codec's default CRF, so the verdict says whether the target holds
+    /// that quality; target-quality mode is sampled at the default CRF
+    /// without searching.
+    pub fn estimate(&self) -> Result<Estimate> {
+        let plan = self.plan()?;
+        let opts = &self.options;
+        if plan.passthrough.is_some() {
+            eprintln!("note: the input already fits, so a run would not encode it");
+        }
+        let settings = match plan.encode {
+            Some(settings) => settings,
+            None => self.plan_encode(&plan.media, &plan.streams, &self.encoder.encoders()?)?,
+        };
+        let rate_control = match opts.rate_control {
+            RateControl::TargetSize { .. } | RateControl::TargetQuality { .. } => {
+                RateControl::Quality { crf: None }
+            }
+            mode => mode,
+        };
+        let encode = EncodePlan {
+            rate_control,
+            ..settings.encode_plan(self, &plan)
+        };
+        let samples = sample::spread(plan.media.duration);
+        eprintln!(
+            "estimating from {} sample(s) at {}",
+            samples.len(),
+            rate_summary(rate_control, opts.codec, settings.video_bitrate)
+        );
+        let mut runs = Vec::new();
+        for sample in samples {
+            let started = Instant::now();
+            let bytes = self.encoder.sample_size(&encode, sample)?;
+            runs.push(estimate::SampleRun {
+                seconds: sample.duration.or(plan.media.duration).unwrap_or(0.0),
+                bytes,
+                encode_seconds: started.elapsed().as_secs_f64(),
+            });
+        }
+        let passes = if opts.rate_control.two_pass() && opts.codec.supports_two_pass() {
+            2
+        } else {
+            1
+        };
+        Ok(Estimate::extrapolate(
+            opts.rate_control
+                .targets_size()
+                .then_some(opts.target_bytes),
+            &runs,
+            plan.media.duration,
+            settings.budget.map_or(0, |b| b.reserved_bytes()),
+            passes,
+        ))
+    }
+
+    /// Budget, audio, first bitrate and output shape for encoding `media`.
+    fn plan_encode(
+        &self,
+        media: &MediaInfo,
+        streams: &StreamSelection,
+        encoders: &[String],
+    ) -> Result<EncodeSettings> {
+        let opts = &self.options;
+        let rate_control = opts.rate_control;
+
+        // A fixed bitrate (or cap) skips the calculation below.
+        let fixed_bitrate = match rate_control {
+            RateControl::TargetSize { .. } => opts.video_bitrate,
+            RateControl::CappedQuality { max_bitrate, .. } => max_bitrate,
+            RateControl::Quality { .. } | RateControl::TargetQuality { .. } => None,
+        };
+        // Default video bitrate if not provided.
+        let mut v_bitrate = fixed_bitrate.unwrap_or(FALLBACK_VIDEO_BITRATE);
+
+        let duration = media.duration;
+        // Only reserve audio budget when there is audio to keep.
+        let audio = match streams.audio {
+            Some(_) => Some(self.audio_settings(media, encoders)?),
+            None => None,
+        };
+        let a_bitrate = audio.map_or(0, |a| a.bitrate);
+        let budget = duration.map(|d| {
+            Budget::estimate(
+                opts.target_bytes,
+                d,
+                media,
+                streams,
+                audio.as_ref(),
+                self.container(),
+            )
+        });
+
+        // If the duration is known, back-calc bitrate to hit target size.
+        let auto = rate_control.targets_size() && budget.is_some() && fixed_bitrate.is_none();
+        if let (true, Some(budget)) = (auto, &budget) {
+            eprintln!("{}", budget);
+            match budget.video_bitrate() {
+                Some(calc) => v_bitrate = self.clamp_bitrate(calc, budget)?,
+                None => {
+                    // Audio, subtitles and muxing alone fill the target.
+                    return Err(Error::TargetUnreachable {
+                        target_bytes: opts.target_bytes,
+                        output_bytes: budget.reserved_bytes(),
+                    });
+                }
+            }
+        }
+        // Constant quality has no bitrate to shape the video around.
+        let planned_bitrate = rate_control.limits_bitrate().then_some(v_bitrate);
+
+        let complexity = match opts.analyze {
+            true => Some(self.analyze(media, streams)?),
+            false => None,
+        };
+        let preset = complexity.map_or(Preset::default(), Complexity::preset);
+        let (fps, video_size) = self.video_shape(media, planned_bitrate, complexity);
+
+        eprintln!(
+            "duration={:.2}s, {}, audio_bitrate={}bps",
+            duration.unwrap_or(0.0),
+            rate_summary(rate_control, opts.codec, v_bitrate),
+            a_bitrate
+        );
+        if let Some((w, h)) = video_size {
+            eprintln!("resolution: {}x{}", w, h);
+        }
+        if let Some(fps) = fps {
+            eprintln!("frame rate: {} fps", fps);
+        }
+        if let Some(complexity) = complexity {
+            eprintln!("complexity: {}; preset {}", complexity, preset);
+        }
+        if let Some(audio) = &audio {
+            eprintln!(
+                "audio: {}, {} channel(s), {} Hz",
+                audio.codec,
+                audio
+                    .channels
+                    .map_or_else(|| "source".to_string(), |c| c.to_string()),
+                audio
+                    .sample_rate
+                    .map_or_else(|| "source".to_string(), |r| r.to_string())
+            );
+        }
+
+        Ok(EncodeSettings {
+            budget,
+            audio,
+            video_bitrate: v_bitrate,
+            // Only an auto-calculated bitrate is adjusted between attempts.
+            adjustable: auto,
+            video_size,
+            fps,
+            preset,
+            complexity,
+        })
+    }
+
+    /// Encode samples of the video at the codec's default CRF with a fast
+    /// preset, and measure how many bits per pixel they needed.
+    fn analyze(&self, media: &MediaInfo, streams: &StreamSelection) -> Result<Complexity> {
+        let codec = self.options.codec;
+        let video = media.video();
+        let pixels = video
+            .and_then(|v| Some(v.width? as u64 * v.height? as u64))
+            .unwrap_or(0);
+        let fps = video.and_then(|v| v.frame_rate()).unwrap_or(30.0);
+        let samples = sample::spread(media.duration);
+        eprintln!("analysing {} sample(s)", samples.len());
+        let plan = EncodePlan {
+            job: self,
+            media,
+            streams,
+            rate_control: RateControl::Quality { crf: None },
+            preset: Preset::Fast,
+            audio: None,
+            video_bitrate: 0,
+            size: None,
+            fps: None,
+        };
+        let mut bytes = 0;
+        let mut seconds = 0.0;
+        for sample in samples {
+            bytes += self.encoder.sample_size(&plan, sample)?;
+            seconds += sample.duration.or(media.duration).unwrap_or(0.0);
+        }
+        Ok(Complexity::measure(bytes, seconds, pixels, fps, codec))
+    }
+
+    /// Clamp the auto-calculated bitrate `calc` to the configured range and
+    /// say when that moves the predicted output off the target. Being
+    /// forced over the target is an error in strict mode; falling short of
+    /// it only ever warns, since the output still fits.
+    fn clamp_bitrate(&self, calc: u64, budget: &Budget) -> Result<u64> {
+        let opts = &self.options;
+        let v_bitrate = calc.clamp(opts.bitrate_floor, opts.bitrate_ceiling);
+        let predicted = (v_bitrate as f64 / 8.0 * budget.duration) as u64 + budget.reserved_bytes();
+        if v_bitrate > calc {
+            eprintln!(
+                "warning: {}bps needed to fit {} bytes is below the {}bps floor; \
+                 predicted output is {} bytes",
+                calc, opts.target_bytes, opts.bitrate_floor, predicted
+            );
+            if opts.strict {
+                return Err(Error::TargetUnreachable {
+                    target_bytes: opts.target_bytes,
+                    output_bytes: predicted,
+                });
+            }
+        } else if v_bitrate < calc {
+            eprintln!(
+                "warning: {}bps would fill {} bytes but the ceiling is {}bps; \
+                 predicted output is {} bytes",
+                calc, opts.target_bytes, opts.bitrate_ceiling, predicted
+            );
+        }
+        Ok(v_bitrate)
+    }
+
+    /// Output frame rate and size for the main video stream at the
+    /// initial bitrate, each `None` when the source's is kept. They are
+    /// kept across attempts so the convergence loop only moves the bitrate.
+    /// Without a planned bitrate only the policies' fixed limits apply.
+    /// A measured `complexity` moves the per-pixel budgets both are
+    /// chosen against.
+    fn video_shape(
+        &self,
+        media: &MediaInfo,
+        v_bitrate: Option<u64>,
+        complexity: Option<Complexity>,
+    ) -> (Option<f64>, Option<(u32, u32)>) {
+        let opts = &self.options;
+        let Some(video) = media.video() else {
+            return (None, None);
+        };
+        let Some(source) = video.display_size() else {
+            return (None, None);
+        };
+        let source_fps = video.frame_rate().unwrap_or(30.0);
+        let (scale, fps_policy) = match v_bitrate {
+            Some(_) => (opts.scale, opts.fps),
+            None => (opts.scale.auto(false), opts.fps.auto(false)),
+        };
+        let v_bitrate = v_bitrate.unwrap_or(0);
+        let codec = opts.codec;
+        let (scale_bpp, fps_bpp) = match complexity {
+            Some(c) => (c.scale_bits_per_pixel(codec), c.fps_bits_per_pixel(codec)),
+            None => (codec.bits_per_pixel(), codec.bits_per_pixel()),
+        };
+
+        // Decide the frame rate against the size the limits alone allow,
+        // then fit the resolution to the frames that remain.
+        let wanted = scale
+            .auto(false)
+            .target_size(source, v_bitrate, source_fps, scale_bpp)
+            .unwrap_or(source);
+        let pixels = wanted.0 as u64 * wanted.1 as u64;
+        let fps = fps_policy.target_fps(source_fps, pixels, v_bitrate, fps_bpp);
+        let size = scale.target_size(source, v_bitrate, fps.unwrap_or(source_fps), scale_bpp);
+        (fps, size)
+    }
+
+    /// Resolve the audio codec for this output and check it can be used.
+    fn audio_settings(&self, media: &MediaInfo, encoders: &[String]) -> Result<AudioSettings> {
+        let opts = &self.options;
+        let container = self.container();
+        let has_encoder = |name: &str| encoders.iter().any(|e| e == name);
+        let codec = opts
+            .audio_codec
+            .resolve(container, opts.audio_bitrate, has_encoder);
+        if !codec.fits(container) {
+            return Err(Error::InvalidArgument(format!(
+                "{} audio cannot be stored in {}",
+                codec, container
+            )));
+        }
+        if let Some(encoder) = codec.encoder() {
+            ffmpeg::require_encoder(encoders, encoder)?;
+        }
+        Ok(AudioSettings::for_bitrate(codec, opts.audio_bitrate, media))
+    }
+}
+
+/// How one encode spends bits, for the log.
+fn rate_summary(mode: RateControl, codec: VideoCodec, v_bitrate: u64) -> String {
+    match mode {
+        RateControl::TargetSize { .. } => format!("video_bitrate={}bps", v_bitrate),
+        RateControl::Quality { crf } => format!("crf={}", crf.unwrap_or(codec.default_crf())),
+        RateControl::CappedQuality { crf, .. } => format!(
+            "crf={}, max video_bitrate={}bps",
+            crf.unwrap_or(codec.default_crf()),
+            v_bitrate
+        ),
+        RateControl::TargetQuality { vmaf, capped } => {
+            let mut summary = format!("crf for VMAF >= {}", vmaf);
+            if capped {
+                summary.push_str(&format!(", max video_bitrate={}bps", v_bitrate));
+            }
+            summary
+        }
+    }
+}
+
+/// Scale the video bitrate by how far the video part of the last output
+/// missed its share of the target, `reserved` bytes going to the rest.
+fn corrected_bitrate(target_bytes: u64, reserved: u64, v_bitrate: u64, size: u64) -> u64 {
+    let wanted = target_bytes as f64 - reserved as f64;
+    let got = size as f64 - reserved as f64;
+    if wanted <= 0.0 || got <= 0.0 {
+        return v_bitrate;
+    }
+    // Aim slightly low so the next attempt lands under the target.
+    (v_bitrate as f64 * wanted / got * 0.98) as u64
+}
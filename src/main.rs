@@ -1,108 +1,478 @@
-use std::process::{Command, Stdio};
-use clap::Parser;
+use std::io::IsTerminal;
+use std::path::Path;
+use std::process::ExitCode;
 
-/// Shrink an MP4 to a target size using ffmpeg re-encoding.
+use clap::error::ErrorKind;
+use clap::{CommandFactory, Parser, ValueEnum};
+use mp4_shrink::batch::{self, BatchOptions};
+use mp4_shrink::scheduler::Scheduler;
+use mp4_shrink::{
+    AudioCodec, Container, Error, FpsPolicy, Passthrough, ProgressStyle, QualityFloor, RateControl,
+    ScalePolicy, ShrinkJob, ShrinkOptions, VideoCodec,
+};
+
+/// Target size when none is given.
+const DEFAULT_TARGET_BYTES: u64 = 10 * 1024 * 1024;
+
+/// VMAF score `--mode vmaf` aims for when none is given.
+const DEFAULT_VMAF: f64 = 93.0;
+
+/// Shrink a video to a target size using ffmpeg re-encoding.
 #[derive(Parser, Debug)]
 #[command(author, version, about)]
 struct Args {
-    /// Input MP4 file path.
+    /// Input video file path, or a directory to shrink every video in.
     input: String,
-    /// Output MP4 file path.
+    /// Output file path, or the output directory for a directory input.
     output: String,
-    /// Target file size in bytes (default 10MB).
-    #[arg(long, default_value_t = 10 * 1024 * 1024)]
-    target_bytes: u64,
-    /// Optional video bitrate (bps). If omitted, auto-calculated.
+    /// Descend into subdirectories of a directory input.
+    #[arg(short, long)]
+    recursive: bool,
+    /// Only shrink files matching this glob (repeatable; default: common video extensions).
+    #[arg(long)]
+    include: Vec<String>,
+    /// Skip files matching this glob (repeatable).
+    #[arg(long)]
+    exclude: Vec<String>,
+    /// Number of files to encode at the same time in batch mode.
+    #[arg(short, long, default_value_t = 1)]
+    jobs: usize,
+    /// ffmpeg threads per encode (default: cores split across --jobs).
+    #[arg(long)]
+    threads: Option<u32>,
+    /// Target file size in bytes, per file (default 10MB). With `--mode
+    /// vmaf` it is only a cap, and only when given.
+    #[arg(long)]
+    target_bytes: Option<u64>,
+    /// Optional video bitrate (bps) for `--mode size`. If omitted, auto-calculated.
     #[arg(long)]
     video_bitrate: Option<u64>,
     /// Audio bitrate (bps).
     #[arg(long, default_value_t = 64_000)]
     audio_bitrate: u64,
+    /// Audio codec; `auto` picks Opus or (HE-)AAC for the container and bitrate.
+    #[arg(long, value_enum, default_value_t = AudioCodecArg::Auto)]
+    audio_codec: AudioCodecArg,
+    /// Video codec; the matching encoder must be in the local ffmpeg build.
+    #[arg(long, value_enum, default_value_t = CodecArg::H264)]
+    codec: CodecArg,
+    /// Output format (default: from the output extension, else mp4).
+    #[arg(long, value_enum)]
+    format: Option<FormatArg>,
+    /// Long-edge limit in pixels (the width of a landscape frame).
+    #[arg(long)]
+    max_width: Option<u32>,
+    /// Short-edge limit in pixels (the height of a landscape frame).
+    #[arg(long)]
+    max_height: Option<u32>,
+    /// Limit on width × height.
+    #[arg(long)]
+    max_pixels: Option<u64>,
+    /// `auto` also shrinks the frame to keep enough bits per pixel for the
+    /// bitrate; `limits` applies only the --max-* limits.
+    #[arg(long, value_enum, default_value_t = ScaleArg::Auto)]
+    scale: ScaleArg,
+    /// Never output more frames per second than this.
+    #[arg(long)]
+    max_fps: Option<f64>,
+    /// `auto` also drops to 30/24/15 fps when the bitrate is badly short;
+    /// `limits` applies only --max-fps.
+    #[arg(long, value_enum, default_value_t = ScaleArg::Auto)]
+    frame_rate: ScaleArg,
+    /// Rate control: `size` hits --target-bytes with an average bitrate,
+    /// `quality` encodes at a constant --crf whatever the size, `capped`
+    /// uses --crf but never exceeds --max-bitrate (default: what fits the
+    /// target), `vmaf` picks the highest CRF that keeps --vmaf on samples.
+    #[arg(long, value_enum, default_value_t = ModeArg::Size)]
+    mode: ModeArg,
+    /// VMAF score (0-100) `--mode vmaf` must reach (default 93).
+    #[arg(long)]
+    vmaf: Option<f64>,
+    /// Encode in two passes so the output lands close to the target size
+    /// (`--mode size` only).
+    #[arg(long)]
+    two_pass: bool,
+    /// Constant rate factor for `--mode quality` and `--mode capped`
+    /// (default: per codec, e.g. 32 for H.264).
+    #[arg(long)]
+    crf: Option<u32>,
+    /// Video bitrate cap (bps) for `--mode capped`.
+    #[arg(long)]
+    max_bitrate: Option<u64>,
+    /// What to do with an input already under the target: `remux` rewraps
+    /// it with `-c copy` (and `+faststart`), `copy` copies the file as is,
+    /// `encode` re-encodes it anyway.
+    #[arg(long, value_enum, default_value_t = UnderTargetArg::Remux)]
+    under_target: UnderTargetArg,
+    /// Lowest video bitrate (bps) the target may be calculated down to.
+    #[arg(long, default_value_t = 200_000)]
+    bitrate_floor: u64,
+    /// Highest video bitrate (bps) the target may be calculated up to.
+    #[arg(long, default_value_t = 1_500_000)]
+    bitrate_ceiling: u64,
+    /// Fail instead of warning when the bitrate floor makes the target unreachable.
+    #[arg(long)]
+    strict: bool,
+    /// Maximum number of encodes while converging on the target size.
+    #[arg(long, default_value_t = 3)]
+    max_attempts: u32,
+    /// Accept outputs this fraction under the target without re-encoding.
+    #[arg(long, default_value_t = 0.1)]
+    tolerance: f64,
+    /// After encoding, score the output against the input with VMAF (if
+    /// ffmpeg has libvmaf), SSIM and PSNR.
+    #[arg(long)]
+    quality_report: bool,
+    /// Warn when the --quality-report VMAF score is below this (0-100).
+    #[arg(long, default_value_t = 80.0)]
+    vmaf_floor: f64,
+    /// Warn when the --quality-report SSIM score is below this (0-1).
+    #[arg(long)]
+    ssim_floor: Option<f64>,
+    /// Warn when the --quality-report PSNR is below this many dB.
+    #[arg(long)]
+    psnr_floor: Option<f64>,
+    /// Encode a few seconds of the input first to judge how hard it is to
+    /// compress, and pick the resolution, frame rate and preset from that.
+    #[arg(long)]
+    analyze: bool,
+    /// Probe and print the budget and the ffmpeg commands, without encoding.
+    /// Two-pass stats go to `mp4_shrink-passlog` in the temp dir.
+    #[arg(long)]
+    dry_run: bool,
+    /// Encode a few sample windows at the planned settings and predict the
+    /// output size and encode time, without writing the output.
+    #[arg(long, conflicts_with = "dry_run")]
+    estimate: bool,
+    /// Progress display; `auto` draws a bar on a terminal, lines otherwise.
+    #[arg(long, value_enum, default_value_t = ProgressArg::Auto)]
+    progress: ProgressArg,
 }
 
-fn main() -> std::io::Result<()> {
-    let args = Args::parse();
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum CodecArg {
+    /// H.264 (libx264).
+    #[value(alias = "x264")]
+    H264,
+    /// H.265/HEVC (libx265).
+    #[value(alias = "hevc", alias = "x265")]
+    H265,
+    /// AV1 (libsvtav1).
+    #[value(alias = "svt-av1")]
+    Av1,
+    /// AV1 (libaom-av1).
+    #[value(alias = "libaom")]
+    Av1Aom,
+    /// VP9 (libvpx-vp9).
+    Vp9,
+}
+
+impl From<CodecArg> for VideoCodec {
+    fn from(arg: CodecArg) -> Self {
+        match arg {
+            CodecArg::H264 => VideoCodec::H264,
+            CodecArg::H265 => VideoCodec::H265,
+            CodecArg::Av1 => VideoCodec::Av1Svt,
+            CodecArg::Av1Aom => VideoCodec::Av1Aom,
+            CodecArg::Vp9 => VideoCodec::Vp9,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum ModeArg {
+    /// Average bitrate aimed at the target size.
+    Size,
+    /// Constant quality.
+    Quality,
+    /// Constant quality under a bitrate cap.
+    Capped,
+    /// Smallest constant quality meeting a VMAF score.
+    Vmaf,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum UnderTargetArg {
+    Remux,
+    Copy,
+    Encode,
+}
 
-    // Default video bitrate if not provided.
-    let mut v_bitrate = args.video_bitrate.unwrap_or(500_000);
-
-    // Probe duration; if available, back-calc bitrate to hit target size.
-    let duration = probe_duration(&args.input).unwrap_or(0.0);
-    if duration > 0.0 && args.video_bitrate.is_none() {
-        // Leave 15% headroom and reserve audio.
-        let reserve = (args.target_bytes as f64 * 0.85)
-            - (args.audio_bitrate as f64 / 8.0 * duration);
-        if reserve > 0.0 {
-            let calc = (reserve * 8.0 / duration) as u64;
-            v_bitrate = calc.clamp(200_000, 1_500_000);
+impl From<UnderTargetArg> for Option<Passthrough> {
+    fn from(arg: UnderTargetArg) -> Self {
+        match arg {
+            UnderTargetArg::Remux => Some(Passthrough::Remux),
+            UnderTargetArg::Copy => Some(Passthrough::Copy),
+            UnderTargetArg::Encode => None,
         }
     }
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum AudioCodecArg {
+    Auto,
+    /// AAC-LC (ffmpeg's aac).
+    Aac,
+    /// HE-AAC (libfdk_aac).
+    HeAac,
+    /// Opus (libopus).
+    Opus,
+}
 
-    eprintln!(
-        "duration={:.2}s, video_bitrate={}bps, audio_bitrate={}bps",
-        duration, v_bitrate, args.audio_bitrate
-    );
-
-    // Encode with H.264/AAC; downscale width to <=640; high CRF for small size.
-    let status = Command::new("ffmpeg")
-        .args([
-            "-y",
-            "-i",
-            &args.input,
-            "-c:v",
-            "libx264",
-            "-preset",
-            "medium",
-            "-b:v",
-            &format!("{}k", v_bitrate / 1000),
-            "-maxrate",
-            &format!("{}k", v_bitrate / 1000),
-            "-bufsize",
-            &format!("{}k", v_bitrate / 500),
-            "-vf",
-            "scale='min(640,iw)':-2",
-            "-c:a",
-            "aac",
-            "-b:a",
-            &format!("{}k", args.audio_bitrate / 1000),
-            "-movflags",
-            "+faststart",
-            "-crf",
-            "32",
-            &args.output,
-        ])
-        .stdout(Stdio::inherit())
-        .stderr(Stdio::inherit())
-        .status()?;
-
-    if !status.success() {
-        eprintln!("ffmpeg failed, exit code: {:?}", status.code());
-        std::process::exit(1);
+impl From<AudioCodecArg> for AudioCodec {
+    fn from(arg: AudioCodecArg) -> Self {
+        match arg {
+            AudioCodecArg::Auto => AudioCodec::Auto,
+            AudioCodecArg::Aac => AudioCodec::Aac,
+            AudioCodecArg::HeAac => AudioCodec::HeAac,
+            AudioCodecArg::Opus => AudioCodec::Opus,
+        }
     }
+}
 
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum FormatArg {
+    Mp4,
+    Webm,
+    Mkv,
+    Mov,
+}
+
+impl From<FormatArg> for Container {
+    fn from(arg: FormatArg) -> Self {
+        match arg {
+            FormatArg::Mp4 => Container::Mp4,
+            FormatArg::Webm => Container::WebM,
+            FormatArg::Mkv => Container::Mkv,
+            FormatArg::Mov => Container::Mov,
+        }
+    }
+}
+
+/// Whether a policy may adapt to the bitrate or only applies its limits.
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum ScaleArg {
+    Auto,
+    Limits,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum ProgressArg {
+    Auto,
+    Bar,
+    Lines,
+    Off,
+}
+
+impl ProgressArg {
+    fn style(self) -> ProgressStyle {
+        match self {
+            ProgressArg::Auto if std::io::stderr().is_terminal() => ProgressStyle::Bar,
+            ProgressArg::Auto => ProgressStyle::Lines,
+            ProgressArg::Bar => ProgressStyle::Bar,
+            ProgressArg::Lines => ProgressStyle::Lines,
+            ProgressArg::Off => ProgressStyle::Off,
+        }
+    }
+}
+
+impl Args {
+    fn options(&self) -> ShrinkOptions {
+        ShrinkOptions::default()
+            .target_bytes(self.target_bytes.unwrap_or(DEFAULT_TARGET_BYTES))
+            .video_bitrate(self.video_bitrate)
+            .audio_bitrate(self.audio_bitrate)
+            .audio_codec(self.audio_codec.into())
+            .codec(self.codec.into())
+            .container(self.format.map(Container::from))
+            .scale(
+                ScalePolicy::default()
+                    .max_width(self.max_width)
+                    .max_height(self.max_height)
+                    .max_pixels(self.max_pixels)
+                    .auto(matches!(self.scale, ScaleArg::Auto)),
+            )
+            .fps(
+                FpsPolicy::default()
+                    .max_fps(self.max_fps)
+                    .auto(matches!(self.frame_rate, ScaleArg::Auto)),
+            )
+            .rate_control(self.rate_control())
+            .under_target(self.under_target.into())
+            .bitrate_floor(self.bitrate_floor)
+            .bitrate_ceiling(self.bitrate_ceiling)
+            .strict(self.strict)
+            .max_attempts(self.max_attempts)
+            .tolerance(self.tolerance)
+            .progress(self.progress.style())
+            .threads(self.threads)
+            .quality_report(self.quality_report)
+            .analyze(self.analyze)
+            .quality_floor(
+                QualityFloor::default()
+                    .vmaf(Some(self.vmaf_floor))
+                    .ssim(self.ssim_floor)
+                    .psnr(self.psnr_floor),
+            )
+    }
+
+    fn rate_control(&self) -> RateControl {
+        match self.mode {
+            ModeArg::Size => RateControl::TargetSize {
+                two_pass: self.two_pass,
+            },
+            ModeArg::Quality => RateControl::Quality { crf: self.crf },
+            ModeArg::Capped => RateControl::CappedQuality {
+                crf: self.crf,
+                max_bitrate: self.max_bitrate,
+            },
+            ModeArg::Vmaf => RateControl::TargetQuality {
+                vmaf: self.vmaf.unwrap_or(DEFAULT_VMAF),
+                capped: self.target_bytes.is_some(),
+            },
+        }
+    }
+
+    /// Reject flags the chosen --mode would silently ignore.
+    fn check_mode(&self) -> Result<(), clap::Error> {
+        let size = self.mode == ModeArg::Size;
+        let ignored = [
+            ("--two-pass", self.two_pass && !size),
+            ("--video-bitrate", self.video_bitrate.is_some() && !size),
+            (
+                "--crf",
+                self.crf.is_some() && matches!(self.mode, ModeArg::Size | ModeArg::Vmaf),
+            ),
+            ("--vmaf", self.vmaf.is_some() && self.mode != ModeArg::Vmaf),
+            (
+                "--max-bitrate",
+                self.max_bitrate.is_some() && self.mode != ModeArg::Capped,
+            ),
+        ];
+        match ignored.iter().find(|(_, set)| *set) {
+            None => Ok(()),
+            Some((flag, _)) => {
+                let mode = self.mode.to_possible_value().expect("no skipped values");
+                Err(Args::command().error(
+                    ErrorKind::ArgumentConflict,
+                    format!("{} does not apply to --mode {}", flag, mode.get_name()),
+                ))
+            }
+        }
+    }
+
+    fn batch_options(&self) -> BatchOptions {
+        BatchOptions::default()
+            .recursive(self.recursive)
+            .include(self.include.clone())
+            .exclude(self.exclude.clone())
+    }
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+    if let Err(e) = args.check_mode() {
+        e.exit();
+    }
+    let result = if Path::new(&args.input).is_dir() {
+        run_batch(&args)
+    } else {
+        run_single(&args).map(|()| ExitCode::SUCCESS)
+    };
+    match result {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::from(e.exit_code())
+        }
+    }
+}
+
+fn run_single(args: &Args) -> Result<(), Error> {
+    let job = ShrinkJob::new(&args.input, &args.output, args.options());
+    if args.dry_run {
+        return dry_run(&job);
+    }
+    if args.estimate {
+        println!("{}", job.estimate()?);
+        return Ok(());
+    }
+    let report = job.run()?;
+    match report.passthrough {
+        Some(done) => eprintln!(
+            "{} -> {} bytes, {} without re-encoding",
+            report.input_bytes, report.output_bytes, done
+        ),
+        None => eprintln!(
+            "{} -> {} bytes in {} attempt(s)",
+            report.input_bytes,
+            report.output_bytes,
+            report.attempts.len()
+        ),
+    }
+    if let Some(quality) = &report.quality {
+        match report.bitrate() {
+            Some(bps) => eprintln!("quality: {} at {}bps overall", quality, bps),
+            None => eprintln!("quality: {}", quality),
+        }
+    }
     Ok(())
 }
 
-/// Read video duration (seconds) via ffprobe.
-fn probe_duration(path: &str) -> Option<f64> {
-    let out = Command::new("ffprobe")
-        .args([
-            "-v",
-            "error",
-            "-show_entries",
-            "format=duration",
-            "-of",
-            "default=noprint_wrappers=1:nokey=1",
-            path,
-        ])
-        .output()
-        .ok()?;
-    if !out.status.success() {
-        return None;
-    }
-    let s = String::from_utf8_lossy(&out.stdout)
-        .trim()
-        .to_string();
-    s.parse::<f64>().ok()
+/// Shrink every matching file and print a summary table, then every
+/// failure in full. The exit code is that of the first failed file, if any.
+fn run_batch(args: &Args) -> Result<ExitCode, Error> {
+    let jobs = batch::collect_jobs(
+        Path::new(&args.input),
+        Path::new(&args.output),
+        &args.batch_options(),
+        &args.options(),
+    )?;
+    if jobs.is_empty() {
+        eprintln!("no matching files in {}", args.input);
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    // --threads is already set on every job; the scheduler only fills in
+    // its default split for jobs without it.
+    let scheduler = Scheduler::new(args.jobs);
+    if args.dry_run || args.estimate {
+        let mut code = ExitCode::SUCCESS;
+        for job in &jobs {
+            println!("# {}", job.input().display());
+            let job = scheduler.prepare(job);
+            let result = match args.estimate {
+                true => job.estimate().map(|estimate| println!("{}", estimate)),
+                false => dry_run(&job),
+            };
+            if let Err(e) = result {
+                eprintln!("error: {}: {}", job.input().display(), e);
+                if code == ExitCode::SUCCESS {
+                    code = ExitCode::from(e.exit_code());
+                }
+            }
+        }
+        return Ok(code);
+    }
+    let report = scheduler.run(&jobs);
+    println!("{}", report);
+    let mut code = ExitCode::SUCCESS;
+    for entry in report.failures() {
+        if let Err(e) = &entry.result {
+            eprintln!("error: {}: {}", entry.input.display(), e);
+            if code == ExitCode::SUCCESS {
+                code = ExitCode::from(e.exit_code());
+            }
+        }
+    }
+    Ok(code)
 }
 
+/// Plan `job` and print the commands it would run, one per line.
+fn dry_run(job: &ShrinkJob) -> Result<(), Error> {
+    let plan = job.plan()?;
+    for line in job.command_lines(&plan) {
+        println!("{}", line);
+    }
+    Ok(())
+}
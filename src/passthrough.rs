@@ -0,0 +1,101 @@
+//! Writing the input out unchanged instead of re-encoding it.
+
+use std::fmt;
+
+use std::path::PathBuf;
+
+use crate::container::Container;
+use crate::error::Result;
+use crate::probe::MediaInfo;
+use crate::streams::StreamSelection;
+use crate::{ShrinkJob, ShrinkOptions};
+
+/// How an input is written out without re-encoding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Passthrough {
+    /// Copy the file byte for byte.
+    Copy,
+    /// Rewrap the streams with `-c copy`, which also moves an MP4's
+    /// index to the front (`+faststart`).
+    #[default]
+    Remux,
+}
+
+impl fmt::Display for Passthrough {
+    /// What happened to the file, e.g. `remuxed`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Passthrough::Copy => "copied",
+            Passthrough::Remux => "remuxed",
+        })
+    }
+}
+
+/// What `mode` amounts to for `job`: a copy into a different container
+/// is remuxed instead, so the output always matches its format.
+pub(crate) fn resolve(job: &ShrinkJob, mode: Passthrough) -> Passthrough {
+    if Container::from_path(job.input()) == Some(job.container()) {
+        mode
+    } else {
+        Passthrough::Remux
+    }
+}
+
+/// Write `job`'s input to its output as [`resolve`]d from `mode` and
+/// return what was done.
+pub(crate) fn pass_through(
+    job: &ShrinkJob,
+    mode: Passthrough,
+    media: &MediaInfo,
+    streams: &StreamSelection,
+) -> Result<Passthrough> {
+    let mode = resolve(job, mode);
+    match mode {
+        Passthrough::Copy => {
+            std::fs::copy(job.input(), job.output())?;
+        }
+        Passthrough::Remux => job.encoder.remux(job, media, streams)?,
+    }
+    Ok(mode)
+}
+
+/// Replace an encoded output with `job`'s input. The input is remuxed
+/// into a temp file beside the output and only renamed over it once
+/// that succeeds, so a failed remux leaves the encode in place.
+pub(crate) fn keep_original(
+    job: &ShrinkJob,
+    media: &MediaInfo,
+    streams: &StreamSelection,
+) -> Result<Passthrough> {
+    if resolve(job, Passthrough::Copy) == Passthrough::Copy {
+        return pass_through(job, Passthrough::Copy, media, streams);
+    }
+    let temp = temp_output(job);
+    let remux = ShrinkJob {
+        output: temp.clone(),
+        options: ShrinkOptions {
+            container: Some(job.container()),
+            ..job.options.clone()
+        },
+        ..job.clone()
+    };
+    let result = job
+        .encoder
+        .remux(&remux, media, streams)
+        .and_then(|()| Ok(std::fs::rename(&temp, job.output())?));
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temp);
+    }
+    result.map(|()| Passthrough::Remux)
+}
+
+/// A hidden file next to `job`'s output with the same extension.
+fn temp_output(job: &ShrinkJob) -> PathBuf {
+    let name = job
+        .output()
+        .file_name()
+        .map_or_else(|| "output".into(), |n| n.to_string_lossy().into_owned());
+    job.output()
+        .with_file_name(format!(".{}.{}.tmp", name, std::process::id()))
+        .with_extension(job.container().extension())
+}
@@ -0,0 +1,388 @@
+//! Media probing via `ffprobe -print_format json`.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::process;
+
+use serde::Deserialize;
+
+use crate::command::{Command, Input};
+use crate::error::{Error, Result};
+use crate::ffmpeg::spawn_error;
+
+/// Container-level facts and every stream of a probed file.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct MediaInfo {
+    /// ffprobe's demuxer name, e.g. `mov,mp4,m4a,3gp,3g2,mj2`.
+    pub format_name: String,
+    /// Duration in seconds, from the container or else the longest stream.
+    pub duration: Option<f64>,
+    /// File size in bytes as reported by ffprobe.
+    pub size: Option<u64>,
+    /// Overall bitrate (bps).
+    pub bit_rate: Option<u64>,
+    /// Container-level metadata tags such as `title`.
+    pub tags: HashMap<String, String>,
+    pub streams: Vec<StreamInfo>,
+}
+
+/// What a stream carries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamKind {
+    Video,
+    Audio,
+    Subtitle,
+    Data,
+    Attachment,
+    Other,
+}
+
+/// One stream of a probed file. Fields ffprobe leaves out are `None`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StreamInfo {
+    /// Absolute stream index, usable as `0:<index>` in `-map`.
+    pub index: u32,
+    pub kind: StreamKind,
+    pub codec_name: Option<String>,
+    /// Coded width before rotation.
+    pub width: Option<u32>,
+    /// Coded height before rotation.
+    pub height: Option<u32>,
+    /// Average frame rate (fps).
+    pub avg_frame_rate: Option<f64>,
+    /// Base frame rate (fps); differs from `avg_frame_rate` for VFR sources.
+    pub r_frame_rate: Option<f64>,
+    /// Stream bitrate (bps).
+    pub bit_rate: Option<u64>,
+    pub pix_fmt: Option<String>,
+    /// Display rotation in degrees, normalised to 0, 90, 180 or 270.
+    pub rotation: u32,
+    /// ISO 639 language tag.
+    pub language: Option<String>,
+    pub disposition: Disposition,
+    pub channels: Option<u32>,
+    pub sample_rate: Option<u32>,
+    /// Stream duration in seconds.
+    pub duration: Option<f64>,
+    /// Frame (or packet) count if the container records it.
+    pub nb_frames: Option<u64>,
+}
+
+/// The stream disposition flags we act on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Disposition {
+    pub default: bool,
+    pub forced: bool,
+    pub comment: bool,
+    pub hearing_impaired: bool,
+    pub visual_impaired: bool,
+    /// Cover art stored as a single-frame video stream.
+    pub attached_pic: bool,
+}
+
+impl MediaInfo {
+    /// The main video stream: the first one that is not cover art.
+    pub fn video(&self) -> Option<&StreamInfo> {
+        self.streams
+            .iter()
+            .find(|s| s.kind == StreamKind::Video && !s.disposition.attached_pic)
+    }
+
+    pub fn audio_streams(&self) -> impl Iterator<Item = &StreamInfo> {
+        self.streams.iter().filter(|s| s.kind == StreamKind::Audio)
+    }
+
+    pub fn has_audio(&self) -> bool {
+        self.audio_streams().next().is_some()
+    }
+
+    /// The audio stream an output keeps: the one marked default, else
+    /// the first.
+    pub fn main_audio(&self) -> Option<&StreamInfo> {
+        self.audio_streams()
+            .find(|s| s.disposition.default)
+            .or_else(|| self.audio_streams().next())
+    }
+}
+
+impl StreamInfo {
+    /// Width and height as displayed, i.e. swapped for 90/270 rotation.
+    pub fn display_size(&self) -> Option<(u32, u32)> {
+        let (w, h) = (self.width?, self.height?);
+        if self.rotation % 180 == 90 {
+            Some((h, w))
+        } else {
+            Some((w, h))
+        }
+    }
+
+    /// Frames per second on average, falling back to the base rate.
+    pub fn frame_rate(&self) -> Option<f64> {
+        self.avg_frame_rate.or(self.r_frame_rate)
+    }
+
+    /// Whether the base and average frame rates disagree noticeably.
+    pub fn is_variable_frame_rate(&self) -> bool {
+        match (self.r_frame_rate, self.avg_frame_rate) {
+            (Some(r), Some(avg)) => (r - avg).abs() / r > 0.01,
+            _ => false,
+        }
+    }
+}
+
+/// The ffprobe command that prints everything [`parse`] reads about `path`.
+pub(crate) fn command(path: &Path) -> Command {
+    Command::ffprobe()
+        .option("v", "error")
+        .option("print_format", "json")
+        .flag("show_format")
+        .flag("show_streams")
+        .input(Input::new(path))
+}
+
+/// Probe `path` with ffprobe and parse every stream.
+pub fn probe(path: &Path) -> Result<MediaInfo> {
+    let command = command(path);
+    let out = process::Command::new(command.program())
+        .args(command.args())
+        .output()
+        .map_err(|e| spawn_error("ffprobe", e))?;
+    let probe_error = |message: String| Error::Probe {
+        path: path.to_path_buf(),
+        message,
+    };
+    if !out.status.success() {
+        return Err(probe_error(
+            String::from_utf8_lossy(&out.stderr).trim().to_string(),
+        ));
+    }
+    parse(&out.stdout).map_err(|e| probe_error(e.to_string()))
+}
+
+/// Parse the JSON printed by `ffprobe -show_format -show_streams`.
+pub fn parse(json: &[u8]) -> serde_json::Result<MediaInfo> {
+    let raw: RawProbe = serde_json::from_slice(json)?;
+    let streams: Vec<StreamInfo> = raw.streams.into_iter().map(StreamInfo::from).collect();
+    let format = raw.format.unwrap_or_default();
+    let duration = number(&format.duration)
+        .filter(|d| *d > 0.0)
+        .or_else(|| streams.iter().filter_map(|s| s.duration).reduce(f64::max));
+    Ok(MediaInfo {
+        format_name: format.format_name.unwrap_or_default(),
+        duration,
+        size: number(&format.size),
+        bit_rate: number(&format.bit_rate),
+        tags: format.tags,
+        streams,
+    })
+}
+
+// ffprobe prints most numbers as strings, and "N/A" when unknown.
+#[derive(Deserialize)]
+struct RawProbe {
+    #[serde(default)]
+    streams: Vec<RawStream>,
+    format: Option<RawFormat>,
+}
+
+#[derive(Deserialize, Default)]
+struct RawFormat {
+    format_name: Option<String>,
+    duration: Option<String>,
+    size: Option<String>,
+    bit_rate: Option<String>,
+    #[serde(default)]
+    tags: HashMap<String, String>,
+}
+
+#[derive(Deserialize)]
+struct RawStream {
+    index: u32,
+    codec_type: Option<String>,
+    codec_name: Option<String>,
+    width: Option<u32>,
+    height: Option<u32>,
+    avg_frame_rate: Option<String>,
+    r_frame_rate: Option<String>,
+    bit_rate: Option<String>,
+    pix_fmt: Option<String>,
+    channels: Option<u32>,
+    sample_rate: Option<String>,
+    duration: Option<String>,
+    nb_frames: Option<String>,
+    #[serde(default)]
+    disposition: HashMap<String, i64>,
+    #[serde(default)]
+    tags: HashMap<String, String>,
+    #[serde(default)]
+    side_data_list: Vec<RawSideData>,
+}
+
+#[derive(Deserialize)]
+struct RawSideData {
+    rotation: Option<f64>,
+}
+
+impl From<RawStream> for StreamInfo {
+    fn from(raw: RawStream) -> Self {
+        let kind = match raw.codec_type.as_deref() {
+            Some("video") => StreamKind::Video,
+            Some("audio") => StreamKind::Audio,
+            Some("subtitle") => StreamKind::Subtitle,
+            Some("data") => StreamKind::Data,
+            Some("attachment") => StreamKind::Attachment,
+            _ => StreamKind::Other,
+        };
+        let flag = |name: &str| raw.disposition.get(name).is_some_and(|v| *v != 0);
+        let disposition = Disposition {
+            default: flag("default"),
+            forced: flag("forced"),
+            comment: flag("comment"),
+            hearing_impaired: flag("hearing_impaired"),
+            visual_impaired: flag("visual_impaired"),
+            attached_pic: flag("attached_pic"),
+        };
+        // Newer ffprobe reports a display matrix, older ones a `rotate` tag.
+        let degrees = raw
+            .side_data_list
+            .iter()
+            .find_map(|sd| sd.rotation)
+            .or_else(|| raw.tags.get("rotate").and_then(|r| r.parse().ok()))
+            .unwrap_or(0.0);
+        StreamInfo {
+            index: raw.index,
+            kind,
+            width: raw.width,
+            height: raw.height,
+            avg_frame_rate: rate(&raw.avg_frame_rate),
+            r_frame_rate: rate(&raw.r_frame_rate),
+            bit_rate: number(&raw.bit_rate),
+            pix_fmt: raw.pix_fmt,
+            rotation: normalise_rotation(degrees),
+            language: raw.tags.get("language").cloned(),
+            disposition,
+            channels: raw.channels,
+            sample_rate: number(&raw.sample_rate),
+            duration: number(&raw.duration),
+            nb_frames: number(&raw.nb_frames),
+            codec_name: raw.codec_name,
+        }
+    }
+}
+
+fn number<T: std::str::FromStr>(s: &Option<String>) -> Option<T> {
+    s.as_deref()?.parse().ok()
+}
+
+/// Parse `num/den` frame rates; `0/0` means unknown.
+fn rate(s: &Option<String>) -> Option<f64> {
+    let s = s.as_deref()?;
+    let (num, den) = s.split_once('/').unwrap_or((s, "1"));
+    let (num, den): (f64, f64) = (num.parse().ok()?, den.parse().ok()?);
+    if num > 0.0 && den > 0.0 {
+        Some(num / den)
+    } else {
+        None
+    }
+}
+
+/// Map any angle (the display matrix reports e.g. -90) onto 0/90/180/270.
+fn normalise_rotation(degrees: f64) -> u32 {
+    let quarter = (degrees / 90.0).round() as i64;
+    (quarter.rem_euclid(4) * 90) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn video(json: &str) -> StreamInfo {
+        let media = parse(json.as_bytes()).unwrap();
+        media.streams.into_iter().next().unwrap()
+    }
+
+    #[test]
+    fn unknown_values_are_none() {
+        let media = parse(
+            br#"{
+                "streams": [{"index": 0, "codec_type": "video",
+                             "avg_frame_rate": "0/0", "r_frame_rate": "N/A",
+                             "bit_rate": "N/A", "nb_frames": "N/A"}],
+                "format": {"duration": "N/A", "size": "N/A", "bit_rate": "N/A"}
+            }"#,
+        )
+        .unwrap();
+        let stream = &media.streams[0];
+        assert_eq!(stream.avg_frame_rate, None);
+        assert_eq!(stream.r_frame_rate, None);
+        assert_eq!(stream.bit_rate, None);
+        assert_eq!(stream.nb_frames, None);
+        assert_eq!(media.duration, None);
+        assert_eq!(media.size, None);
+        assert_eq!(media.bit_rate, None);
+    }
+
+    #[test]
+    fn rates_are_fractions() {
+        assert_eq!(rate(&Some("30000/1001".into())), Some(30000.0 / 1001.0));
+        assert_eq!(rate(&Some("25".into())), Some(25.0));
+        assert_eq!(rate(&Some("0/0".into())), None);
+        assert_eq!(rate(&Some("N/A".into())), None);
+        assert_eq!(rate(&None), None);
+    }
+
+    #[test]
+    fn display_matrix_rotation_is_normalised() {
+        let stream = video(
+            r#"{"streams": [{"index": 0, "codec_type": "video",
+                             "width": 1920, "height": 1080,
+                             "side_data_list": [{"side_data_type": "Display Matrix",
+                                                 "rotation": -90}]}]}"#,
+        );
+        assert_eq!(stream.rotation, 270);
+        assert_eq!(stream.display_size(), Some((1080, 1920)));
+        assert_eq!(normalise_rotation(450.0), 90);
+        assert_eq!(normalise_rotation(-180.0), 180);
+    }
+
+    #[test]
+    fn rotate_tag_is_the_fallback() {
+        let stream = video(
+            r#"{"streams": [{"index": 0, "codec_type": "video",
+                             "side_data_list": [{"side_data_type": "Other"}],
+                             "tags": {"rotate": "90"}}]}"#,
+        );
+        assert_eq!(stream.rotation, 90);
+    }
+
+    #[test]
+    fn missing_format_duration_uses_the_longest_stream() {
+        let media = parse(
+            br#"{
+                "streams": [
+                    {"index": 0, "codec_type": "video", "duration": "59.9"},
+                    {"index": 1, "codec_type": "audio", "duration": "60.2"}
+                ],
+                "format": {"format_name": "matroska,webm"}
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(media.duration, Some(60.2));
+    }
+
+    #[test]
+    fn cover_art_is_not_the_main_video() {
+        let media = parse(
+            br#"{"streams": [
+                {"index": 0, "codec_type": "video", "codec_name": "mjpeg",
+                 "disposition": {"default": 0, "attached_pic": 1}},
+                {"index": 1, "codec_type": "video", "codec_name": "h264",
+                 "disposition": {"default": 1, "attached_pic": 0}}
+            ]}"#,
+        )
+        .unwrap();
+        assert!(media.streams[0].disposition.attached_pic);
+        assert!(!media.streams[1].disposition.attached_pic);
+        assert_eq!(media.video().map(|v| v.index), Some(1));
+    }
+}
@@ -0,0 +1,258 @@
+//! Progress reporting from `ffmpeg -progress pipe:1` output.
+
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+/// How encode progress is shown on stderr.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ProgressStyle {
+    /// A single redrawn bar with ETA and predicted size, for terminals.
+    Bar,
+    /// A plain line every 10% (or 30 seconds), for logs and CI.
+    Lines,
+    /// Nothing.
+    #[default]
+    Off,
+}
+
+/// One snapshot of an ffmpeg run, emitted at the end of each progress block.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub(crate) struct Progress {
+    /// Output timestamp reached, in seconds.
+    pub out_time: f64,
+    /// Bytes written to the output so far.
+    pub total_size: u64,
+    /// Encoding speed as a multiple of real time.
+    pub speed: Option<f64>,
+    /// Frames encoded per second.
+    pub fps: Option<f64>,
+    /// Whether ffmpeg reported `progress=end`.
+    pub done: bool,
+}
+
+/// Accumulates `key=value` lines until a `progress=` line closes a block.
+#[derive(Default)]
+pub(crate) struct ProgressParser {
+    current: Progress,
+}
+
+impl ProgressParser {
+    pub(crate) fn line(&mut self, line: &str) -> Option<Progress> {
+        let (key, value) = line.trim().split_once('=')?;
+        let value = value.trim();
+        match key {
+            "out_time_us" => {
+                if let Ok(us) = value.parse::<i64>() {
+                    self.current.out_time = us.max(0) as f64 / 1_000_000.0;
+                }
+            }
+            "total_size" => {
+                if let Ok(size) = value.parse() {
+                    self.current.total_size = size;
+                }
+            }
+            "speed" => self.current.speed = value.trim_end_matches('x').parse().ok(),
+            "fps" => self.current.fps = value.parse().ok().filter(|f| *f > 0.0),
+            "progress" => {
+                self.current.done = value == "end";
+                return Some(self.current);
+            }
+            _ => {}
+        }
+        None
+    }
+}
+
+/// Periodic-line interval for [`ProgressStyle::Lines`] when 10% steps are slow.
+const LINE_INTERVAL: Duration = Duration::from_secs(30);
+const BAR_WIDTH: usize = 30;
+
+/// Renders [`Progress`] snapshots for one ffmpeg run against the probed duration.
+pub(crate) struct Reporter {
+    style: ProgressStyle,
+    label: String,
+    duration: Option<f64>,
+    last_step: Option<u32>,
+    last_line: Instant,
+}
+
+impl Reporter {
+    pub(crate) fn new(style: ProgressStyle, label: &str, duration: Option<f64>) -> Self {
+        Reporter {
+            style,
+            label: label.to_string(),
+            duration,
+            last_step: None,
+            last_line: Instant::now(),
+        }
+    }
+
+    pub(crate) fn update(&mut self, p: &Progress) {
+        match self.style {
+            ProgressStyle::Off => {}
+            ProgressStyle::Bar => {
+                let _ = write!(io::stderr(), "\r{}\x1b[K", self.status(p, true));
+            }
+            ProgressStyle::Lines => {
+                let step = self.fraction(p).map(|f| (f * 10.0) as u32);
+                let due = self.last_line.elapsed() >= LINE_INTERVAL;
+                if step != self.last_step || due || p.done {
+                    self.last_step = step;
+                    self.last_line = Instant::now();
+                    eprintln!("{}", self.status(p, false));
+                }
+            }
+        }
+    }
+
+    /// End the bar's line so later output starts on a fresh one.
+    pub(crate) fn finish(&self) {
+        if self.style == ProgressStyle::Bar {
+            eprintln!();
+        }
+    }
+
+    fn fraction(&self, p: &Progress) -> Option<f64> {
+        let duration = self.duration?;
+        Some(if p.done {
+            1.0
+        } else {
+            (p.out_time / duration).clamp(0.0, 1.0)
+        })
+    }
+
+    fn status(&self, p: &Progress, bar: bool) -> String {
+        let mut s = format!("{}: ", self.label);
+        match self.fraction(p) {
+            Some(f) => {
+                if bar {
+                    let filled = (f * BAR_WIDTH as f64) as usize;
+                    s += &format!(
+                        "[{}{}] ",
+                        "#".repeat(filled),
+                        "-".repeat(BAR_WIDTH - filled)
+                    );
+                }
+                s += &format!("{:5.1}%", f * 100.0);
+                if let (Some(speed), Some(duration)) = (p.speed.filter(|s| *s > 0.0), self.duration)
+                {
+                    let eta = (duration - p.out_time).max(0.0) / speed;
+                    s += &format!("  ETA {}", clock(eta));
+                }
+            }
+            None => s += &format!("{} encoded", clock(p.out_time)),
+        }
+        if let Some(speed) = p.speed {
+            s += &format!("  {:.2}x", speed);
+        }
+        if let Some(fps) = p.fps {
+            s += &format!("  {:.0} fps", fps);
+        }
+        // Early sizes are dominated by headers; wait for 5% before predicting.
+        if let Some(f) = self.fraction(p).filter(|f| *f >= 0.05) {
+            if p.total_size > 0 {
+                s += &format!("  ~{}", megabytes(p.total_size as f64 / f));
+            }
+        }
+        s
+    }
+}
+
+fn clock(seconds: f64) -> String {
+    let s = seconds.round() as u64;
+    if s >= 3600 {
+        format!("{}:{:02}:{:02}", s / 3600, s / 60 % 60, s % 60)
+    } else {
+        format!("{:02}:{:02}", s / 60, s % 60)
+    }
+}
+
+pub(crate) fn megabytes(bytes: f64) -> String {
+    format!("{:.1} MB", bytes / 1_000_000.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(lines: &[&str]) -> Vec<Progress> {
+        let mut parser = ProgressParser::default();
+        lines.iter().filter_map(|l| parser.line(l)).collect()
+    }
+
+    #[test]
+    fn block_closes_on_progress_line() {
+        let blocks = block(&[
+            "fps=24.5",
+            "total_size=1048576",
+            "out_time_us=2500000",
+            "speed=1.5x",
+            "progress=continue",
+            "out_time_us=4000000",
+            "progress=end",
+        ]);
+        assert_eq!(
+            blocks,
+            vec![
+                Progress {
+                    out_time: 2.5,
+                    total_size: 1_048_576,
+                    speed: Some(1.5),
+                    fps: Some(24.5),
+                    done: false,
+                },
+                Progress {
+                    out_time: 4.0,
+                    total_size: 1_048_576,
+                    speed: Some(1.5),
+                    fps: Some(24.5),
+                    done: true,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_values_are_none() {
+        let blocks = block(&[
+            "fps=0.00",
+            "speed=N/A",
+            "total_size=N/A",
+            "out_time_us=-9223372036854775807",
+            "progress=continue",
+        ]);
+        assert_eq!(blocks, vec![Progress::default()]);
+    }
+
+    #[test]
+    fn lines_without_a_value_are_ignored() {
+        assert_eq!(block(&["", "frame", "bitrate=N/A"]), vec![]);
+    }
+
+    #[test]
+    fn fraction_follows_out_time() {
+        let reporter = Reporter::new(ProgressStyle::Off, "encode", Some(10.0));
+        let at = |out_time, done| Progress {
+            out_time,
+            done,
+            ..Progress::default()
+        };
+        assert_eq!(reporter.fraction(&at(2.5, false)), Some(0.25));
+        assert_eq!(reporter.fraction(&at(12.0, false)), Some(1.0));
+        assert_eq!(reporter.fraction(&at(9.0, true)), Some(1.0));
+        let unknown = Reporter::new(ProgressStyle::Off, "encode", None);
+        assert_eq!(unknown.fraction(&at(2.5, false)), None);
+    }
+
+    #[test]
+    fn size_is_predicted_from_five_percent() {
+        let reporter = Reporter::new(ProgressStyle::Off, "encode", Some(100.0));
+        let at = |out_time| Progress {
+            out_time,
+            total_size: 1_000_000,
+            ..Progress::default()
+        };
+        assert!(!reporter.status(&at(4.0), false).contains('~'));
+        assert!(reporter.status(&at(5.0), false).ends_with("~20.0 MB"));
+    }
+}
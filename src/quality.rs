@@ -0,0 +1,228 @@
+//! Perceptual quality of an output measured against its input.
+
+use std::fmt;
+
+use crate::error::Result;
+
+/// How an output scores against its input scaled to the output's size
+/// and frame rate. A score is `None` when it could not be measured, e.g.
+/// VMAF without an ffmpeg built with libvmaf.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct QualityScores {
+    /// VMAF, 0 to 100.
+    pub vmaf: Option<f64>,
+    /// SSIM over all planes, 0 to 1.
+    pub ssim: Option<f64>,
+    /// Average PSNR in dB; infinite for identical frames.
+    pub psnr: Option<f64>,
+}
+
+/// Scores below which a shrink is warned about; `None` checks nothing.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QualityFloor {
+    pub vmaf: Option<f64>,
+    pub ssim: Option<f64>,
+    pub psnr: Option<f64>,
+}
+
+impl Default for QualityFloor {
+    /// VMAF 80, roughly where viewers start to notice the loss.
+    fn default() -> Self {
+        QualityFloor {
+            vmaf: Some(80.0),
+            ssim: None,
+            psnr: None,
+        }
+    }
+}
+
+impl QualityFloor {
+    pub fn vmaf(mut self, score: Option<f64>) -> Self {
+        self.vmaf = score;
+        self
+    }
+
+    pub fn ssim(mut self, score: Option<f64>) -> Self {
+        self.ssim = score;
+        self
+    }
+
+    pub fn psnr(mut self, db: Option<f64>) -> Self {
+        self.psnr = db;
+        self
+    }
+}
+
+impl QualityScores {
+    /// One message per measured score below its `floor`, such as
+    /// `VMAF 71.30 is below the floor of 80`.
+    pub fn breaches(&self, floor: &QualityFloor) -> Vec<String> {
+        [
+            ("VMAF", self.vmaf, floor.vmaf),
+            ("SSIM", self.ssim, floor.ssim),
+            ("PSNR", self.psnr, floor.psnr),
+        ]
+        .into_iter()
+        .filter_map(|(name, score, floor)| match (score, floor) {
+            (Some(score), Some(floor)) if score < floor => Some(format!(
+                "{} {} is below the floor of {}",
+                name,
+                Score(name, score),
+                floor
+            )),
+            _ => None,
+        })
+        .collect()
+    }
+}
+
+impl fmt::Display for QualityScores {
+    /// The measured scores, e.g. `VMAF 85.21, SSIM 0.9620, PSNR 38.41 dB`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let scores: Vec<String> = [
+            ("VMAF", self.vmaf),
+            ("SSIM", self.ssim),
+            ("PSNR", self.psnr),
+        ]
+        .into_iter()
+        .filter_map(|(name, score)| Some(format!("{} {}", name, Score(name, score?))))
+        .collect();
+        if scores.is_empty() {
+            f.write_str("no scores")
+        } else {
+            f.write_str(&scores.join(", "))
+        }
+    }
+}
+
+/// A score at the precision its metric is usually quoted to.
+struct Score(&'static str, f64);
+
+impl fmt::Display for Score {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            "SSIM" => write!(f, "{:.4}", self.1),
+            "PSNR" => write!(f, "{:.2} dB", self.1),
+            _ => write!(f, "{:.2}", self.1),
+        }
+    }
+}
+
+/// Read the summaries the `libvmaf`, `ssim` and `psnr` filters log at
+/// the end of a run:
+///
+/// ```text
+/// VMAF score: 85.213
+/// [Parsed_ssim_7 @ 0x...] SSIM Y:0.96 (14.0) U:0.97 (15.9) V:0.97 (15.8) All:0.962 (14.2)
+/// [Parsed_psnr_8 @ 0x...] PSNR y:37.9 u:41.2 v:41.5 average:38.41 min:35.2 max:42.0
+/// ```
+pub(crate) fn parse(log: &str) -> QualityScores {
+    let mut scores = QualityScores::default();
+    for line in log.lines() {
+        if let Some((_, rest)) = line.split_once("VMAF score") {
+            // libvmaf 1.x logged `VMAF score = x`, 2.x `VMAF score: x`.
+            scores.vmaf = rest.trim_start_matches([':', '=', ' ']).parse().ok();
+        } else if line.contains("] SSIM ") {
+            scores.ssim = field(line, "All:");
+        } else if line.contains("] PSNR ") {
+            scores.psnr = field(line, "average:");
+        }
+    }
+    scores
+}
+
+/// The number after `key` in `line`; `inf` parses as infinity.
+fn field(line: &str, key: &str) -> Option<f64> {
+    let (_, rest) = line.split_once(key)?;
+    rest.split_whitespace().next()?.parse().ok()
+}
+
+/// The highest CRF in `range` whose `score` reaches `threshold`, found by
+/// binary search on the assumption that scores fall as the CRF rises.
+/// When even the lowest CRF misses, that one is used with a warning.
+pub(crate) fn search_crf(
+    range: (u32, u32),
+    threshold: f64,
+    mut score: impl FnMut(u32) -> Result<f64>,
+) -> Result<u32> {
+    let (mut low, mut high) = range;
+    let mut best = None;
+    while low <= high {
+        let crf = low + (high - low) / 2;
+        let vmaf = score(crf)?;
+        eprintln!("crf {}: VMAF {:.2}", crf, vmaf);
+        if vmaf >= threshold {
+            best = Some(crf);
+            low = crf + 1;
+        } else if crf == range.0 {
+            break;
+        } else {
+            high = crf - 1;
+        }
+    }
+    Ok(best.unwrap_or_else(|| {
+        eprintln!(
+            "warning: even crf {} scores below VMAF {}; using it",
+            range.0, threshold
+        );
+        range.0
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SSIM: &str = "[Parsed_ssim_7 @ 0x5581] SSIM Y:0.961 (14.1) U:0.970 (15.2) \
+                        V:0.972 (15.5) All:0.9634 (14.4)";
+    const PSNR: &str = "[Parsed_psnr_8 @ 0x5582] PSNR y:37.90 u:41.20 v:41.50 \
+                        average:38.41 min:35.20 max:42.00";
+
+    #[test]
+    fn reads_every_summary() {
+        let log = format!(
+            "frame=  240 fps=60\nVMAF score: 85.213\n{}\n{}\n",
+            SSIM, PSNR
+        );
+        assert_eq!(
+            parse(&log),
+            QualityScores {
+                vmaf: Some(85.213),
+                ssim: Some(0.9634),
+                psnr: Some(38.41),
+            }
+        );
+    }
+
+    #[test]
+    fn reads_the_libvmaf_1_summary() {
+        let log = "[libvmaf @ 0x5583] VMAF score = 91.5\n";
+        assert_eq!(parse(log).vmaf, Some(91.5));
+    }
+
+    #[test]
+    fn identical_frames_have_infinite_psnr() {
+        let log = "[Parsed_psnr_1 @ 0x5584] PSNR y:inf u:inf v:inf average:inf min:inf max:inf";
+        assert_eq!(parse(log).psnr, Some(f64::INFINITY));
+    }
+
+    #[test]
+    fn a_missing_metric_is_none() {
+        assert_eq!(
+            parse(SSIM),
+            QualityScores {
+                ssim: Some(0.9634),
+                ..QualityScores::default()
+            }
+        );
+        assert_eq!(parse(""), QualityScores::default());
+    }
+
+    #[test]
+    fn field_needs_a_number_after_its_key() {
+        assert_eq!(field("a:1 average:2.5 b:3", "average:"), Some(2.5));
+        assert_eq!(field("average:", "average:"), None);
+        assert_eq!(field("average:N/A", "average:"), None);
+        assert_eq!(field("min:1", "average:"), None);
+    }
+}
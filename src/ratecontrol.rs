@@ -0,0 +1,93 @@
+//! Video rate-control modes.
+
+use crate::codec::VideoCodec;
+use crate::command::Options;
+
+/// How the video encoder spends bits.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RateControl {
+    /// Average bitrate chosen to hit `target_bytes`, re-encoding until the
+    /// output converges. Two passes spread the bits far more evenly.
+    TargetSize { two_pass: bool },
+    /// Constant quality; the output is as large as the content needs.
+    /// `None` uses the codec's default CRF.
+    Quality { crf: Option<u32> },
+    /// Constant quality, but never above `max_bitrate` (bps). Without an
+    /// explicit cap, the bitrate that fits `target_bytes` is used and
+    /// lowered if the output still comes out too large. An explicit cap
+    /// ignores `target_bytes`.
+    CappedQuality {
+        crf: Option<u32>,
+        max_bitrate: Option<u64>,
+    },
+    /// Constant quality at the highest CRF whose VMAF on sample segments
+    /// of the input still reaches `vmaf`. With `capped`, `target_bytes`
+    /// also caps the output as in [`RateControl::CappedQuality`].
+    TargetQuality { vmaf: f64, capped: bool },
+}
+
+impl Default for RateControl {
+    fn default() -> Self {
+        RateControl::TargetSize { two_pass: false }
+    }
+}
+
+impl RateControl {
+    /// Whether the output size is steered towards `target_bytes`. An
+    /// explicit cap replaces the target.
+    pub fn targets_size(self) -> bool {
+        match self {
+            RateControl::CappedQuality {
+                max_bitrate: Some(_),
+                ..
+            } => false,
+            mode => mode.limits_bitrate(),
+        }
+    }
+
+    /// Whether encodes are held to a bitrate, as an average or a cap.
+    pub(crate) fn limits_bitrate(self) -> bool {
+        match self {
+            RateControl::Quality { .. } => false,
+            RateControl::TargetQuality { capped, .. } => capped,
+            _ => true,
+        }
+    }
+
+    pub fn two_pass(self) -> bool {
+        matches!(self, RateControl::TargetSize { two_pass: true })
+    }
+
+    /// The constant-quality mode [`RateControl::TargetQuality`] encodes
+    /// with once its search settles on `crf`; other modes are unchanged.
+    pub(crate) fn with_crf(self, crf: u32) -> RateControl {
+        match self {
+            RateControl::TargetQuality { capped: false, .. } => {
+                RateControl::Quality { crf: Some(crf) }
+            }
+            RateControl::TargetQuality { capped: true, .. } => RateControl::CappedQuality {
+                crf: Some(crf),
+                max_bitrate: None,
+            },
+            mode => mode,
+        }
+    }
+
+    /// Video encoder options for this mode. `v_bitrate` is the average for
+    /// [`RateControl::TargetSize`] and the cap for
+    /// [`RateControl::CappedQuality`]; pure quality mode ignores it.
+    /// [`RateControl::TargetQuality`] has not chosen its CRF yet and shows
+    /// the codec's default.
+    pub(crate) fn options(self, codec: VideoCodec, v_bitrate: u64) -> Options {
+        match self {
+            RateControl::TargetQuality { .. } => {
+                self.with_crf(codec.default_crf()).options(codec, v_bitrate)
+            }
+            RateControl::TargetSize { .. } => codec.bitrate_options(v_bitrate),
+            RateControl::Quality { crf } => codec.crf_options(crf.unwrap_or(codec.default_crf())),
+            RateControl::CappedQuality { crf, .. } => {
+                codec.capped_crf_options(crf.unwrap_or(codec.default_crf()), v_bitrate)
+            }
+        }
+    }
+}
@@ -0,0 +1,47 @@
+//! Short windows of the input, encoded to predict the whole file.
+
+/// Seconds in each sample window.
+const SAMPLE_SECONDS: f64 = 5.0;
+
+/// Windows taken from an input long enough to hold them.
+const SAMPLE_COUNT: usize = 3;
+
+/// A stretch of the input in seconds.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sample {
+    pub start: f64,
+    /// `None` runs to the end of the input.
+    pub duration: Option<f64>,
+}
+
+impl Sample {
+    /// The whole input.
+    pub fn whole() -> Self {
+        Sample {
+            start: 0.0,
+            duration: None,
+        }
+    }
+}
+
+/// Windows centred on evenly spaced points of an input lasting
+/// `duration` seconds, so openings and credits do not dominate. An input
+/// too short (or of unknown length) to leave gaps between them is
+/// sampled whole.
+pub(crate) fn spread(duration: Option<f64>) -> Vec<Sample> {
+    let Some(duration) = duration else {
+        return vec![Sample::whole()];
+    };
+    if duration < 2.0 * SAMPLE_COUNT as f64 * SAMPLE_SECONDS {
+        return vec![Sample::whole()];
+    }
+    (0..SAMPLE_COUNT)
+        .map(|i| {
+            let centre = duration * (2 * i + 1) as f64 / (2 * SAMPLE_COUNT) as f64;
+            Sample {
+                start: centre - SAMPLE_SECONDS / 2.0,
+                duration: Some(SAMPLE_SECONDS),
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,97 @@
+//! Choosing the output resolution.
+
+/// Short edge the automatic mode never scales below.
+const MIN_SHORT_EDGE: u32 = 144;
+
+/// Limits on the output frame size. Width and height limits describe a
+/// landscape frame and are swapped for portrait sources, so `max_width`
+/// always bounds the long edge and `max_height` the short one.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScalePolicy {
+    /// Long-edge limit in pixels.
+    pub max_width: Option<u32>,
+    /// Short-edge limit in pixels.
+    pub max_height: Option<u32>,
+    /// Limit on width × height.
+    pub max_pixels: Option<u64>,
+    /// Also shrink until each pixel gets the codec's bits-per-pixel budget
+    /// (see [`crate::VideoCodec::bits_per_pixel`]) at the planned bitrate, more
+    /// for content that is hard to compress.
+    pub auto: bool,
+}
+
+impl Default for ScalePolicy {
+    fn default() -> Self {
+        ScalePolicy {
+            max_width: None,
+            max_height: None,
+            max_pixels: None,
+            auto: true,
+        }
+    }
+}
+
+impl ScalePolicy {
+    pub fn max_width(mut self, pixels: Option<u32>) -> Self {
+        self.max_width = pixels;
+        self
+    }
+
+    pub fn max_height(mut self, pixels: Option<u32>) -> Self {
+        self.max_height = pixels;
+        self
+    }
+
+    pub fn max_pixels(mut self, pixels: Option<u64>) -> Self {
+        self.max_pixels = pixels;
+        self
+    }
+
+    pub fn auto(mut self, enabled: bool) -> Self {
+        self.auto = enabled;
+        self
+    }
+
+    /// Output size for a source displayed at `source` (width, height), or
+    /// `None` to keep the source size. Never upscales; both dimensions are
+    /// even so every chroma format can encode them. `bits_per_pixel` is the
+    /// per-pixel budget the automatic mode keeps, normally the codec's.
+    pub fn target_size(
+        &self,
+        source: (u32, u32),
+        video_bitrate: u64,
+        fps: f64,
+        bits_per_pixel: f64,
+    ) -> Option<(u32, u32)> {
+        let (w, h) = (source.0 as f64, source.1 as f64);
+        if w <= 0.0 || h <= 0.0 {
+            return None;
+        }
+        let (long, short) = (w.max(h), w.min(h));
+
+        let mut factor: f64 = 1.0;
+        if let Some(max) = self.max_width {
+            factor = factor.min(max as f64 / long);
+        }
+        if let Some(max) = self.max_height {
+            factor = factor.min(max as f64 / short);
+        }
+        if let Some(max) = self.max_pixels {
+            factor = factor.min((max as f64 / (w * h)).sqrt());
+        }
+        if self.auto && fps > 0.0 {
+            let pixels = video_bitrate as f64 / (fps * bits_per_pixel);
+            let floor = MIN_SHORT_EDGE as f64 / short;
+            factor = factor.min((pixels / (w * h)).sqrt().max(floor));
+        }
+
+        if factor >= 1.0 {
+            return None;
+        }
+        Some((even(w * factor), even(h * factor)))
+    }
+}
+
+fn even(x: f64) -> u32 {
+    ((x / 2.0).round() as u32).max(1) * 2
+}
@@ -0,0 +1,100 @@
+//! Running many shrink jobs side by side.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+use crate::batch::{self, BatchEntry, BatchReport};
+use crate::{ProgressStyle, ShrinkJob};
+
+/// Runs jobs on a fixed number of worker threads, each driving one ffmpeg
+/// at a time with its own `-threads` budget.
+#[derive(Debug, Clone)]
+pub struct Scheduler {
+    concurrency: usize,
+    threads_per_job: Option<u32>,
+}
+
+impl Scheduler {
+    /// `concurrency` simultaneous encodes. Unless overridden with
+    /// [`Scheduler::threads_per_job`], parallel runs split the machine's
+    /// cores evenly between jobs so they do not oversubscribe it.
+    pub fn new(concurrency: usize) -> Self {
+        let concurrency = concurrency.max(1);
+        let threads_per_job = if concurrency > 1 {
+            let cores = thread::available_parallelism().map_or(1, |n| n.get());
+            Some((cores / concurrency).max(1) as u32)
+        } else {
+            None
+        };
+        Scheduler {
+            concurrency,
+            threads_per_job,
+        }
+    }
+
+    /// ffmpeg `-threads` for every job that does not set its own;
+    /// `None` lets the encoder decide.
+    pub fn threads_per_job(mut self, threads: Option<u32>) -> Self {
+        self.threads_per_job = threads;
+        self
+    }
+
+    /// Run every job, creating output directories as needed. A failed file
+    /// is recorded and the other jobs carry on; entries keep job order.
+    pub fn run(&self, jobs: &[ShrinkJob]) -> BatchReport {
+        let next = AtomicUsize::new(0);
+        let done = AtomicUsize::new(0);
+        let slots: Vec<Mutex<Option<BatchEntry>>> = jobs.iter().map(|_| Mutex::new(None)).collect();
+
+        thread::scope(|scope| {
+            for _ in 0..self.concurrency.min(jobs.len()) {
+                scope.spawn(|| loop {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(job) = jobs.get(i) else {
+                        break;
+                    };
+                    let entry = batch::run_one(&self.prepare(job));
+                    let finished = done.fetch_add(1, Ordering::Relaxed) + 1;
+                    let status = if entry.result.is_ok() {
+                        "done"
+                    } else {
+                        "failed"
+                    };
+                    eprintln!(
+                        "[{}/{}] {} {}",
+                        finished,
+                        jobs.len(),
+                        status,
+                        job.input().display()
+                    );
+                    *slots[i].lock().unwrap() = Some(entry);
+                });
+            }
+        });
+
+        BatchReport {
+            entries: slots
+                .into_iter()
+                .filter_map(|slot| slot.into_inner().unwrap())
+                .collect(),
+        }
+    }
+
+    /// `job` as [`Scheduler::run`] runs it: with the thread budget
+    /// applied, and falling back from a redrawn bar to plain lines when
+    /// several jobs would be drawing over each other.
+    pub fn prepare(&self, job: &ShrinkJob) -> ShrinkJob {
+        let mut options = job.options().clone();
+        if options.threads.is_none() {
+            options.threads = self.threads_per_job;
+        }
+        if self.concurrency > 1 && options.progress == ProgressStyle::Bar {
+            options.progress = ProgressStyle::Lines;
+        }
+        ShrinkJob {
+            options,
+            ..job.clone()
+        }
+    }
+}
@@ -0,0 +1,63 @@
+//! Which input streams an output keeps.
+
+use crate::command::{Output, StreamType};
+use crate::container::Container;
+use crate::probe::{MediaInfo, StreamKind};
+
+/// Subtitle codecs that convert between text formats.
+const TEXT_SUBTITLES: &[&str] = &["subrip", "srt", "ass", "ssa", "mov_text", "webvtt", "text"];
+
+/// Input stream indices mapped into the output, in output order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StreamSelection {
+    pub video: u32,
+    /// The main audio stream (see [`MediaInfo::main_audio`]).
+    pub audio: Option<u32>,
+    /// Subtitles the container can carry: text subtitles everywhere,
+    /// and bitmap ones too in Matroska.
+    pub subtitles: Vec<u32>,
+}
+
+impl StreamSelection {
+    /// Streams to keep from `media`, or `None` if it has no video stream.
+    /// Cover art, data streams, attachments and every other audio
+    /// stream are dropped.
+    pub fn for_output(media: &MediaInfo, container: Container) -> Option<Self> {
+        let subtitles = media
+            .streams
+            .iter()
+            .filter(|s| s.kind == StreamKind::Subtitle)
+            .filter(|s| {
+                container == Container::Mkv
+                    || s.codec_name
+                        .as_deref()
+                        .is_some_and(|c| TEXT_SUBTITLES.contains(&c))
+            })
+            .map(|s| s.index)
+            .collect();
+        Some(StreamSelection {
+            video: media.video()?.index,
+            audio: media.main_audio().map(|s| s.index),
+            subtitles,
+        })
+    }
+
+    /// Add the video stream to `output`.
+    pub(crate) fn map_video(&self, output: Output) -> Output {
+        output.map(format!("0:{}", self.video))
+    }
+
+    /// Add the audio and subtitle streams to `output`, converting the
+    /// subtitles for `container`.
+    pub(crate) fn map_others(&self, output: Output, container: Container) -> Output {
+        let mut output = self
+            .audio
+            .iter()
+            .chain(&self.subtitles)
+            .fold(output, |out, index| out.map(format!("0:{}", index)));
+        if !self.subtitles.is_empty() {
+            output = output.codec(StreamType::Subtitle, container.subtitle_codec());
+        }
+        output
+    }
+}
@@ -0,0 +1,72 @@
+//! Turning a directory of inputs into jobs.
+
+mod common;
+
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+use common::Scratch;
+use mp4_shrink::batch::{self, BatchOptions};
+use mp4_shrink::{Container, Error, ShrinkOptions};
+
+/// `scratch/in` holding an empty file per name, and `scratch/out`.
+fn inputs(scratch: &Scratch, names: &[&str]) -> (PathBuf, PathBuf) {
+    let input = scratch.path("in");
+    std::fs::create_dir_all(&input).unwrap();
+    for name in names {
+        File::create(input.join(name)).unwrap();
+    }
+    (input, scratch.path("out"))
+}
+
+fn outputs(input: &Path, output: &Path, options: &ShrinkOptions) -> Vec<String> {
+    batch::collect_jobs(input, output, &BatchOptions::default(), options)
+        .unwrap()
+        .iter()
+        .map(|job| {
+            let name = job.output().strip_prefix(output).unwrap();
+            name.to_string_lossy().into_owned()
+        })
+        .collect()
+}
+
+#[test]
+fn outputs_take_the_container_extension() {
+    let scratch = Scratch::new("batch-plain");
+    let (input, output) = inputs(&scratch, &["a.avi", "b.mov", "c.mkv"]);
+
+    let names = outputs(&input, &output, &ShrinkOptions::default());
+    assert_eq!(names, ["a.mp4", "b.mov", "c.mkv"]);
+}
+
+#[test]
+fn colliding_outputs_keep_the_source_extension() {
+    let scratch = Scratch::new("batch-collide");
+    let (input, output) = inputs(&scratch, &["clip.avi", "clip.mp4", "other.mov"]);
+
+    let names = outputs(&input, &output, &ShrinkOptions::default());
+    assert_eq!(names, ["clip.avi.mp4", "clip.mp4", "other.mov"]);
+
+    let mp4 = ShrinkOptions::default().container(Some(Container::Mp4));
+    let (input, output) = inputs(&scratch, &["clip.mov"]);
+    let names = outputs(&input, &output, &mp4);
+    assert_eq!(
+        names,
+        ["clip.avi.mp4", "clip.mov.mp4", "clip.mp4", "other.mp4"]
+    );
+}
+
+#[test]
+fn outputs_that_still_collide_are_refused() {
+    let scratch = Scratch::new("batch-refuse");
+    let (input, output) = inputs(&scratch, &["clip.avi", "clip.mp4", "clip.avi.mp4"]);
+
+    let err = batch::collect_jobs(
+        &input,
+        &output,
+        &BatchOptions::default(),
+        &ShrinkOptions::default(),
+    )
+    .unwrap_err();
+    assert!(matches!(err, Error::InvalidArgument(_)));
+}
@@ -0,0 +1,29 @@
+//! Helpers shared by the integration tests.
+
+use std::fs;
+use std::path::PathBuf;
+
+/// A directory under the system temp dir, removed on drop.
+pub struct Scratch {
+    dir: PathBuf,
+}
+
+impl Scratch {
+    /// `name` keeps tests running in parallel out of each other's way.
+    pub fn new(name: &str) -> Self {
+        let dir =
+            std::env::temp_dir().join(format!("mp4_shrink-test-{}-{}", std::process::id(), name));
+        fs::create_dir_all(&dir).unwrap();
+        Scratch { dir }
+    }
+
+    pub fn path(&self, file: &str) -> PathBuf {
+        self.dir.join(file)
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
@@ -0,0 +1,327 @@
+//! End-to-end runs of the CLI on clips synthesised with ffmpeg's `lavfi`
+//! sources. Needs ffmpeg and ffprobe with libx264 and aac on `PATH`, but
+//! no network or media files. Ignored by default; run them with
+//! `cargo test --test lavfi -- --ignored`, which fails without ffmpeg.
+
+mod common;
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+use common::Scratch;
+use mp4_shrink::{probe, MediaInfo};
+
+/// The tolerance passed to the CLI, and how far under the target an
+/// output may land.
+const TOLERANCE: f64 = 0.2;
+
+/// A synthetic input: a noisy test pattern, so it is far from free to
+/// encode, with an optional audio source.
+struct Clip {
+    width: u32,
+    height: u32,
+    fps: u32,
+    seconds: u32,
+    /// A lavfi audio source such as `sine=frequency=440`.
+    audio: Option<&'static str>,
+    /// Display rotation in degrees, set on the container without
+    /// touching the pixels.
+    rotation: u32,
+}
+
+impl Clip {
+    fn new(width: u32, height: u32, fps: u32, seconds: u32) -> Self {
+        Clip {
+            width,
+            height,
+            fps,
+            seconds,
+            audio: None,
+            rotation: 0,
+        }
+    }
+
+    fn audio(mut self, source: &'static str) -> Self {
+        self.audio = Some(source);
+        self
+    }
+
+    fn rotation(mut self, degrees: u32) -> Self {
+        self.rotation = degrees;
+        self
+    }
+
+    /// Write the clip to `scratch/in.mp4`.
+    fn generate(&self, scratch: &Scratch) -> PathBuf {
+        let path = scratch.path("in.mp4");
+        let plain = scratch.path("plain.mp4");
+        let video = format!(
+            "testsrc2=size={}x{}:rate={}:duration={},noise=alls=40:allf=t",
+            self.width, self.height, self.fps, self.seconds
+        );
+        let mut args: Vec<String> = vec!["-f".into(), "lavfi".into(), "-i".into(), video];
+        if let Some(audio) = self.audio {
+            let source = format!("{}:duration={}", audio, self.seconds);
+            args.extend(["-f".into(), "lavfi".into(), "-i".into(), source]);
+            args.extend(["-c:a".into(), "aac".into(), "-b:a".into(), "128k".into()]);
+        }
+        args.extend(
+            ["-c:v", "libx264", "-preset", "ultrafast", "-b:v", "3M"]
+                .into_iter()
+                .chain(["-pix_fmt", "yuv420p", "-shortest"])
+                .map(String::from),
+        );
+        let target = if self.rotation == 0 { &path } else { &plain };
+        ffmpeg(args.iter().map(String::as_str).chain([path_str(target)]));
+        if self.rotation == 0 {
+            return path;
+        }
+
+        // ffmpeg 6 sets the display matrix with an input option; older
+        // builds write the `rotate` tag instead.
+        let degrees = self.rotation.to_string();
+        let rotated = try_ffmpeg(&[
+            "-display_rotation",
+            &degrees,
+            "-i",
+            path_str(&plain),
+            "-c",
+            "copy",
+            path_str(&path),
+        ]) || try_ffmpeg(&[
+            "-i",
+            path_str(&plain),
+            "-c",
+            "copy",
+            "-metadata:s:v:0",
+            &format!("rotate={}", degrees),
+            path_str(&path),
+        ]);
+        let kept = rotated
+            && probe::probe(&path).is_ok_and(|media| {
+                media
+                    .video()
+                    .is_some_and(|v| v.rotation % 180 == self.rotation % 180)
+            });
+        assert!(kept, "this ffmpeg cannot write a rotated MP4");
+        path
+    }
+}
+
+/// Fail unless ffmpeg and ffprobe are usable.
+fn require_ffmpeg() {
+    let runs = |program: &str| {
+        Command::new(program)
+            .arg("-version")
+            .output()
+            .is_ok_and(|out| out.status.success())
+    };
+    assert!(
+        runs("ffmpeg") && runs("ffprobe"),
+        "ffmpeg and ffprobe are not on PATH"
+    );
+    let encoders = Command::new("ffmpeg")
+        .args(["-hide_banner", "-encoders"])
+        .output()
+        .map(|out| String::from_utf8_lossy(&out.stdout).into_owned())
+        .unwrap_or_default();
+    let listed = |name: &str| {
+        encoders
+            .lines()
+            .any(|line| line.split_whitespace().nth(1) == Some(name))
+    };
+    assert!(
+        listed("libx264") && listed("aac"),
+        "ffmpeg lacks libx264 or aac"
+    );
+}
+
+fn path_str(path: &Path) -> &str {
+    path.to_str().expect("temp paths are UTF-8")
+}
+
+fn try_ffmpeg(args: &[&str]) -> bool {
+    Command::new("ffmpeg")
+        .args(["-y", "-hide_banner", "-nostdin", "-loglevel", "error"])
+        .args(args)
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+fn ffmpeg<'a>(args: impl IntoIterator<Item = &'a str>) {
+    let args: Vec<&str> = args.into_iter().collect();
+    assert!(try_ffmpeg(&args), "ffmpeg {:?} failed", args);
+}
+
+/// Run the CLI on `input` with `extra` options, expecting success.
+fn shrink(input: &Path, output: &Path, target_bytes: u64, extra: &[&str]) -> Output {
+    let out = Command::new(env!("CARGO_BIN_EXE_mp4_shrink"))
+        .arg(input)
+        .arg(output)
+        .args(["--target-bytes", &target_bytes.to_string()])
+        .args(["--tolerance", &TOLERANCE.to_string()])
+        .args(["--max-attempts", "4", "--progress", "off"])
+        .args(extra)
+        .output()
+        .unwrap();
+    assert!(
+        out.status.success(),
+        "mp4_shrink failed:\n{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    out
+}
+
+/// Decode every frame of `path`, failing on any error ffmpeg reports.
+fn assert_decodable(path: &Path) {
+    let out = Command::new("ffmpeg")
+        .args(["-hide_banner", "-nostdin", "-v", "error", "-i"])
+        .arg(path)
+        .args(["-f", "null", "-"])
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(
+        out.status.success() && stderr.trim().is_empty(),
+        "{} does not decode cleanly:\n{}",
+        path.display(),
+        stderr
+    );
+}
+
+fn assert_fits(path: &Path, target_bytes: u64) {
+    let size = std::fs::metadata(path).unwrap().len();
+    let floor = (target_bytes as f64 * (1.0 - TOLERANCE)) as u64;
+    assert!(
+        (floor..=target_bytes).contains(&size),
+        "{} bytes is outside {}..={}",
+        size,
+        floor,
+        target_bytes
+    );
+}
+
+/// Probe the output and check what every output shares with its input:
+/// one video stream, the audio if there was any, and the duration.
+fn assert_streams(path: &Path, input: &MediaInfo) -> MediaInfo {
+    let media = probe::probe(path).unwrap();
+    assert!(media.video().is_some(), "no video in {}", path.display());
+    assert_eq!(media.has_audio(), input.has_audio());
+    let (got, want) = (media.duration.unwrap(), input.duration.unwrap());
+    assert!((got - want).abs() < 0.5, "duration {} vs {}", got, want);
+    media
+}
+
+/// Generate `clip`, shrink it to `target_bytes` and check the result.
+fn shrink_clip(name: &str, clip: Clip, target_bytes: u64) -> MediaInfo {
+    require_ffmpeg();
+    let scratch = Scratch::new(name);
+    let input = clip.generate(&scratch);
+    let output = scratch.path("out.mp4");
+    let input_media = probe::probe(&input).unwrap();
+    assert!(std::fs::metadata(&input).unwrap().len() > target_bytes);
+
+    shrink(&input, &output, target_bytes, &[]);
+    assert_fits(&output, target_bytes);
+    assert_decodable(&output);
+    assert_streams(&output, &input_media)
+}
+
+#[test]
+#[ignore = "needs ffmpeg; run with --ignored"]
+fn shrinks_video_with_a_tone() {
+    let clip = Clip::new(1280, 720, 30, 8).audio("sine=frequency=440");
+    shrink_clip("tone", clip, 400_000);
+}
+
+#[test]
+#[ignore = "needs ffmpeg; run with --ignored"]
+fn shrinks_video_without_audio() {
+    let clip = Clip::new(854, 480, 25, 6);
+    shrink_clip("silent", clip, 200_000);
+}
+
+#[test]
+#[ignore = "needs ffmpeg; run with --ignored"]
+fn shrinks_high_frame_rate_video_with_noise() {
+    let clip = Clip::new(1280, 720, 60, 5).audio("anoisesrc=color=pink");
+    let media = shrink_clip("noise", clip, 500_000);
+    // About 0.7 Mbps of video cannot carry 720p60: the frame rate steps
+    // down and the frame shrinks to what remains.
+    let video = media.video().unwrap();
+    let fps = video.frame_rate().unwrap();
+    assert!(
+        [30.0, 24.0, 15.0]
+            .iter()
+            .any(|step| (fps - step).abs() < 0.5),
+        "frame rate {} did not step down",
+        fps
+    );
+    assert!(video.width.unwrap() < 1280, "{:?}", video.width);
+}
+
+#[test]
+#[ignore = "needs ffmpeg; run with --ignored"]
+fn keeps_a_rotated_video_upright() {
+    let clip = Clip::new(640, 360, 30, 6)
+        .audio("sine=frequency=1000")
+        .rotation(90);
+    let media = shrink_clip("rotated", clip, 250_000);
+    let (w, h) = media.video().unwrap().display_size().unwrap();
+    assert!(h > w, "{}x{} is not portrait", w, h);
+}
+
+#[test]
+#[ignore = "needs ffmpeg; run with --ignored"]
+fn remuxes_an_input_already_within_the_target() {
+    require_ffmpeg();
+    let scratch = Scratch::new("small");
+    let clip = Clip::new(320, 240, 24, 3).audio("sine=frequency=440");
+    let input = clip.generate(&scratch);
+    let output = scratch.path("out.mp4");
+    let target_bytes = 50_000_000;
+
+    let out = shrink(&input, &output, target_bytes, &[]);
+    assert!(String::from_utf8_lossy(&out.stderr).contains("remuxed without re-encoding"));
+    assert!(std::fs::metadata(&output).unwrap().len() <= target_bytes);
+    assert_decodable(&output);
+    assert_streams(&output, &probe::probe(&input).unwrap());
+}
+
+#[test]
+#[ignore = "needs ffmpeg; run with --ignored"]
+fn reports_quality_against_the_input() {
+    require_ffmpeg();
+    let scratch = Scratch::new("quality");
+    let clip = Clip::new(640, 360, 30, 4).audio("sine=frequency=440");
+    let input = clip.generate(&scratch);
+    let output = scratch.path("out.mp4");
+
+    let out = shrink(&input, &output, 200_000, &["--quality-report"]);
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    let line = stderr
+        .lines()
+        .find(|l| l.starts_with("quality: "))
+        .unwrap_or_else(|| panic!("no quality line in:\n{}", stderr));
+    assert!(
+        line.contains("SSIM 0.") && line.contains("PSNR "),
+        "{}",
+        line
+    );
+}
+
+#[test]
+#[ignore = "needs ffmpeg; run with --ignored"]
+fn estimates_without_writing_the_output() {
+    require_ffmpeg();
+    let scratch = Scratch::new("estimate");
+    let clip = Clip::new(640, 360, 30, 4).audio("sine=frequency=440");
+    let input = clip.generate(&scratch);
+    let output = scratch.path("out.mp4");
+
+    let out = shrink(&input, &output, 200_000, &["--estimate"]);
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(stdout.contains("confidence"), "{}", stdout);
+    assert!(stdout.contains("200000 bytes is"), "{}", stdout);
+    assert!(!output.exists());
+}
@@ -0,0 +1,487 @@
+//! Budgeting and the convergence loop, driven by [`FakeBackend`] so no
+//! media or ffmpeg is needed.
+
+mod common;
+
+use std::fs::File;
+use std::sync::Arc;
+
+use common::Scratch;
+use mp4_shrink::{
+    probe, Error, FakeBackend, FakeCall, Passthrough, Preset, QualityScores, RateControl, Sample,
+    ShrinkJob, ShrinkOptions, ShrinkReport, Verdict,
+};
+
+const TARGET: u64 = 5_000_000;
+
+/// 100 s of 720p30 H.264 with stereo AAC.
+const MEDIA: &str = r#"{
+    "streams": [
+        {"index": 0, "codec_type": "video", "codec_name": "h264",
+         "width": 1280, "height": 720,
+         "avg_frame_rate": "30/1", "r_frame_rate": "30/1"},
+        {"index": 1, "codec_type": "audio", "codec_name": "aac",
+         "channels": 2, "sample_rate": "48000",
+         "disposition": {"default": 1}}
+    ],
+    "format": {"format_name": "mov,mp4,m4a,3gp,3g2,mj2", "duration": "100.0"}
+}"#;
+
+/// A scratch directory holding `in.mp4` of `input_bytes` (all zeros).
+fn scratch(name: &str, input_bytes: u64) -> Scratch {
+    let scratch = Scratch::new(name);
+    File::create(scratch.path("in.mp4"))
+        .unwrap()
+        .set_len(input_bytes)
+        .unwrap();
+    scratch
+}
+
+fn job(scratch: &Scratch, options: ShrinkOptions, backend: &Arc<FakeBackend>) -> ShrinkJob {
+    ShrinkJob::new(scratch.path("in.mp4"), scratch.path("out.mp4"), options)
+        .prober(backend.clone())
+        .encoder(backend.clone())
+}
+
+fn backend(sizes: &[u64]) -> Arc<FakeBackend> {
+    let media = probe::parse(MEDIA.as_bytes()).unwrap();
+    Arc::new(FakeBackend::new(media).output_sizes(sizes.iter().copied()))
+}
+
+fn options() -> ShrinkOptions {
+    ShrinkOptions::default().target_bytes(TARGET)
+}
+
+fn encoded_bitrates(backend: &FakeBackend) -> Vec<u64> {
+    backend
+        .calls()
+        .into_iter()
+        .filter_map(|call| match call {
+            FakeCall::Encode { video_bitrate } => Some(video_bitrate),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn first_attempt_spends_the_video_budget() {
+    let scratch = scratch("budget", 50_000_000);
+    let fake = backend(&[]);
+    let report = job(&scratch, options(), &fake).run().unwrap();
+
+    let budget = report.budget.unwrap();
+    assert_eq!(budget.target_bytes, TARGET);
+    assert!(budget.reserved_bytes() > 100 * 64_000 / 8);
+    let expected = budget.video_bitrate().unwrap();
+    assert_eq!(encoded_bitrates(&fake), [expected]);
+    assert_eq!(report.attempts.len(), 1);
+    assert!(report.output_bytes <= TARGET);
+}
+
+#[test]
+fn overshoot_is_retried_at_a_lower_bitrate() {
+    let scratch = scratch("overshoot", 50_000_000);
+    let fake = backend(&[TARGET * 13 / 10, TARGET * 97 / 100]);
+    let report = job(&scratch, options(), &fake).run().unwrap();
+
+    let bitrates = encoded_bitrates(&fake);
+    assert_eq!(bitrates.len(), 2);
+    assert!(bitrates[1] < bitrates[0]);
+    assert_eq!(report.output_bytes, TARGET * 97 / 100);
+    assert_eq!(report.attempts[1].video_bitrate, Some(bitrates[1]));
+}
+
+#[test]
+fn undershoot_beyond_the_tolerance_is_retried_higher() {
+    let scratch = scratch("undershoot", 50_000_000);
+    let fake = backend(&[TARGET / 2, TARGET * 95 / 100]);
+    job(&scratch, options(), &fake).run().unwrap();
+
+    let bitrates = encoded_bitrates(&fake);
+    assert_eq!(bitrates.len(), 2);
+    assert!(bitrates[1] > bitrates[0]);
+}
+
+#[test]
+fn gives_up_after_max_attempts() {
+    let scratch = scratch("unreachable", 50_000_000);
+    let fake = backend(&[TARGET * 11 / 10; 3]);
+    let err = job(&scratch, options(), &fake).run().unwrap_err();
+
+    assert!(matches!(
+        err,
+        Error::TargetUnreachable {
+            target_bytes: TARGET,
+            output_bytes,
+        } if output_bytes == TARGET * 11 / 10
+    ));
+    assert_eq!(encoded_bitrates(&fake).len(), 3);
+}
+
+#[test]
+fn corrected_bitrate_stays_within_the_floor() {
+    let scratch = scratch("floor", 50_000_000);
+    let fake = backend(&[TARGET * 20, TARGET * 20, TARGET * 20]);
+    let opts = options().bitrate_floor(250_000);
+    job(&scratch, opts, &fake).run().unwrap_err();
+
+    // The second attempt hits the floor, so there is no third.
+    assert_eq!(encoded_bitrates(&fake)[1..], [250_000]);
+}
+
+#[test]
+fn strict_floor_fails_before_encoding() {
+    let scratch = scratch("strict", 50_000_000);
+    let fake = backend(&[]);
+    let opts = options().target_bytes(1_500_000).strict(true);
+    let err = job(&scratch, opts, &fake).run().unwrap_err();
+
+    assert!(matches!(err, Error::TargetUnreachable { .. }));
+    assert!(fake.calls().is_empty());
+}
+
+#[test]
+fn audio_alone_over_the_target_fails_before_encoding() {
+    let scratch = scratch("audio", 50_000_000);
+    let fake = backend(&[]);
+    let opts = options().target_bytes(500_000).audio_bitrate(96_000);
+    let err = job(&scratch, opts, &fake).run().unwrap_err();
+
+    assert!(matches!(err, Error::TargetUnreachable { .. }));
+    assert!(fake.calls().is_empty());
+}
+
+#[test]
+fn input_within_the_target_is_remuxed() {
+    let scratch = scratch("remux", 4_000_000);
+    let fake = backend(&[]);
+    let report = job(&scratch, options(), &fake).run().unwrap();
+
+    assert_eq!(fake.calls(), [FakeCall::Remux]);
+    assert_eq!(report.passthrough, Some(Passthrough::Remux));
+    assert_eq!(report.output_bytes, 4_000_000);
+    assert!(report.attempts.is_empty());
+}
+
+#[test]
+fn output_over_the_input_is_refused() {
+    let scratch = scratch("same-file", 4_000_000);
+    let fake = backend(&[]);
+    let input = scratch.path("in.mp4");
+    let opts = options().under_target(Some(Passthrough::Copy));
+    let job = ShrinkJob::new(&input, scratch.path(".").join("in.mp4"), opts)
+        .prober(fake.clone())
+        .encoder(fake.clone());
+    let err = job.run().unwrap_err();
+
+    assert!(matches!(err, Error::InvalidArgument(_)));
+    assert!(fake.calls().is_empty());
+    assert_eq!(std::fs::metadata(&input).unwrap().len(), 4_000_000);
+}
+
+#[test]
+fn oversized_remux_falls_back_to_encoding() {
+    let scratch = scratch("remux-over", 4_900_000);
+    let fake = backend(&[TARGET + 1, TARGET * 95 / 100]);
+    let report = job(&scratch, options(), &fake).run().unwrap();
+
+    assert!(matches!(
+        fake.calls()[..],
+        [FakeCall::Remux, FakeCall::Encode { .. }]
+    ));
+    assert_eq!(report.passthrough, None);
+}
+
+#[test]
+fn larger_encode_keeps_the_original() {
+    let scratch = scratch("larger", 3_000_000);
+    let fake = backend(&[TARGET * 95 / 100]);
+    let opts = options().under_target(None);
+    let report = job(&scratch, opts, &fake).run().unwrap();
+
+    assert_eq!(report.passthrough, Some(Passthrough::Copy));
+    assert_eq!(report.output_bytes, 3_000_000);
+}
+
+fn mkv_job(scratch: &Scratch, options: ShrinkOptions, backend: &Arc<FakeBackend>) -> ShrinkJob {
+    ShrinkJob::new(scratch.path("in.mp4"), scratch.path("out.mkv"), options)
+        .prober(backend.clone())
+        .encoder(backend.clone())
+}
+
+#[test]
+fn larger_encode_remuxes_the_original_into_another_container() {
+    let scratch = scratch("larger-mkv", 3_000_000);
+    let fake = backend(&[TARGET * 95 / 100]);
+    let opts = options().under_target(None);
+    let report = mkv_job(&scratch, opts, &fake).run().unwrap();
+
+    assert_eq!(report.passthrough, Some(Passthrough::Remux));
+    assert_eq!(report.output_bytes, 3_000_000);
+    assert_eq!(std::fs::read_dir(scratch.path("")).unwrap().count(), 2);
+}
+
+#[test]
+fn failed_remux_of_the_original_keeps_the_encode() {
+    let scratch = scratch("larger-fail", 3_000_000);
+    let media = probe::parse(MEDIA.as_bytes()).unwrap();
+    let fake = Arc::new(
+        FakeBackend::new(media)
+            .output_sizes([TARGET * 95 / 100])
+            .remux_fails(true),
+    );
+    let opts = options().under_target(None);
+    let report = mkv_job(&scratch, opts, &fake).run().unwrap();
+
+    assert_eq!(report.passthrough, None);
+    assert_eq!(report.output_bytes, TARGET * 95 / 100);
+    let written = std::fs::metadata(scratch.path("out.mkv")).unwrap().len();
+    assert_eq!(written, TARGET * 95 / 100);
+    assert_eq!(std::fs::read_dir(scratch.path("")).unwrap().count(), 2);
+}
+
+#[test]
+fn quality_mode_encodes_once() {
+    let scratch = scratch("quality", 50_000_000);
+    let fake = backend(&[TARGET * 3]);
+    let opts = options().rate_control(RateControl::Quality { crf: None });
+    let report = job(&scratch, opts, &fake).run().unwrap();
+
+    assert_eq!(report.attempts.len(), 1);
+    assert_eq!(report.attempts[0].video_bitrate, None);
+    assert!(report.budget.is_some());
+}
+
+fn explicit_cap() -> RateControl {
+    RateControl::CappedQuality {
+        crf: Some(28),
+        max_bitrate: Some(800_000),
+    }
+}
+
+#[test]
+fn explicit_cap_encodes_an_input_under_the_target() {
+    let scratch = scratch("cap-small", 4_000_000);
+    let fake = backend(&[3_000_000]);
+    let opts = options().rate_control(explicit_cap());
+    let report = job(&scratch, opts, &fake).run().unwrap();
+
+    assert_eq!(
+        fake.calls(),
+        [FakeCall::Encode {
+            video_bitrate: 800_000
+        }]
+    );
+    assert_eq!(report.passthrough, None);
+}
+
+#[test]
+fn explicit_cap_may_exceed_the_target() {
+    let scratch = scratch("cap-large", 50_000_000);
+    let fake = backend(&[TARGET * 2]);
+    let opts = options().rate_control(explicit_cap());
+    let report = job(&scratch, opts, &fake).run().unwrap();
+
+    assert_eq!(report.output_bytes, TARGET * 2);
+    assert_eq!(report.attempts[0].video_bitrate, Some(800_000));
+}
+
+#[test]
+fn missing_video_encoder_is_reported() {
+    let scratch = scratch("encoder", 50_000_000);
+    let media = probe::parse(MEDIA.as_bytes()).unwrap();
+    let fake = Arc::new(FakeBackend::new(media).encoders(&["aac"]));
+    let err = job(&scratch, options(), &fake).run().unwrap_err();
+
+    assert!(matches!(err, Error::MissingEncoder { name: "libx264" }));
+}
+
+#[test]
+fn quality_report_scores_the_final_encode() {
+    let scratch = scratch("quality-report", 50_000_000);
+    let scores = QualityScores {
+        vmaf: Some(71.5),
+        ssim: Some(0.93),
+        psnr: Some(33.2),
+    };
+    let media = probe::parse(MEDIA.as_bytes()).unwrap();
+    let fake = Arc::new(FakeBackend::new(media).quality(scores));
+    let opts = options().quality_report(true);
+    let report = job(&scratch, opts.clone(), &fake).run().unwrap();
+
+    // A score under the floor only warns.
+    assert_eq!(report.quality, Some(scores));
+    assert_eq!(fake.calls().last(), Some(&FakeCall::MeasureQuality));
+    assert_eq!(
+        scores.breaches(&opts.quality_floor),
+        ["VMAF 71.50 is below the floor of 80"]
+    );
+}
+
+#[test]
+fn quality_report_skips_a_passthrough() {
+    let scratch = scratch("quality-remux", 4_000_000);
+    let fake = backend(&[]);
+    let opts = options().quality_report(true);
+    let report = job(&scratch, opts, &fake).run().unwrap();
+
+    assert_eq!(report.quality, None);
+    assert_eq!(fake.calls(), [FakeCall::Remux]);
+}
+
+fn sampled_crfs(backend: &FakeBackend) -> Vec<u32> {
+    let mut crfs: Vec<u32> = backend
+        .calls()
+        .into_iter()
+        .filter_map(|call| match call {
+            FakeCall::SampleQuality { crf, .. } => Some(crf),
+            _ => None,
+        })
+        .collect();
+    crfs.dedup();
+    crfs
+}
+
+#[test]
+fn target_quality_encodes_at_the_highest_passing_crf() {
+    let scratch = scratch("vmaf", 50_000_000);
+    let fake = backend(&[TARGET * 3]);
+    let opts = options().rate_control(RateControl::TargetQuality {
+        vmaf: 93.0,
+        capped: false,
+    });
+    let report = job(&scratch, opts, &fake).run().unwrap();
+
+    // The default curve scores 120 - crf, so 27 is the last to reach 93.
+    assert_eq!(report.crf, Some(27));
+    assert_eq!(report.attempts.len(), 1);
+    assert_eq!(report.attempts[0].video_bitrate, None);
+    assert!(sampled_crfs(&fake).contains(&28));
+    // 100 s of input gives three 5 s windows per CRF tried.
+    let windows: Vec<Sample> = fake
+        .calls()
+        .into_iter()
+        .filter_map(|call| match call {
+            FakeCall::SampleQuality { crf: 27, sample } => Some(sample),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(windows.len(), 3);
+    assert!(windows.iter().all(|w| w.duration == Some(5.0)));
+}
+
+#[test]
+fn target_quality_falls_back_to_the_lowest_crf() {
+    let scratch = scratch("vmaf-low", 50_000_000);
+    let media = probe::parse(MEDIA.as_bytes()).unwrap();
+    let fake = Arc::new(FakeBackend::new(media).sample_vmaf(|_| 60.0));
+    let opts = options().rate_control(RateControl::TargetQuality {
+        vmaf: 93.0,
+        capped: false,
+    });
+    let report = job(&scratch, opts, &fake).run().unwrap();
+
+    assert_eq!(report.crf, Some(12));
+}
+
+#[test]
+fn capped_target_quality_converges_under_the_target() {
+    let scratch = scratch("vmaf-capped", 50_000_000);
+    let fake = backend(&[TARGET * 13 / 10, TARGET * 9 / 10]);
+    let opts = options().rate_control(RateControl::TargetQuality {
+        vmaf: 93.0,
+        capped: true,
+    });
+    let report = job(&scratch, opts, &fake).run().unwrap();
+
+    let caps = encoded_bitrates(&fake);
+    assert_eq!(caps.len(), 2);
+    assert!(caps[1] < caps[0]);
+    assert_eq!(report.crf, Some(27));
+    assert_eq!(report.output_bytes, TARGET * 9 / 10);
+}
+
+fn analyzed(name: &str, bits_per_pixel: f64) -> (ShrinkReport, Arc<FakeBackend>) {
+    let scratch = scratch(name, 50_000_000);
+    let media = probe::parse(MEDIA.as_bytes()).unwrap();
+    let fake = Arc::new(FakeBackend::new(media).sample_bits_per_pixel(bits_per_pixel));
+    // Twice the usual target, so hard content can afford its full frame rate.
+    let opts = options().target_bytes(TARGET * 2).analyze(true);
+    let report = job(&scratch, opts, &fake).run().unwrap();
+    (report, fake)
+}
+
+#[test]
+fn analysis_samples_before_encoding() {
+    let (report, fake) = analyzed("analyze", 0.024);
+
+    let calls = fake.calls();
+    assert_eq!(calls.len(), 4);
+    assert!(calls[..3]
+        .iter()
+        .all(|c| matches!(c, FakeCall::SampleSize { sample } if sample.duration == Some(5.0))));
+    assert!(matches!(calls[3], FakeCall::Encode { .. }));
+    let complexity = report.complexity.unwrap();
+    assert!((complexity.factor - 1.0).abs() < 0.01);
+    assert_eq!(complexity.preset(), Preset::Medium);
+}
+
+#[test]
+fn easy_content_keeps_more_resolution_than_hard_content() {
+    let (plain, _) = analyzed("analyze-plain", 0.024);
+    let (easy, _) = analyzed("analyze-easy", 0.008);
+    let (hard, _) = analyzed("analyze-hard", 0.06);
+
+    let width = |report: &ShrinkReport| report.video_size.map_or(1280, |(w, _)| w);
+    assert!(width(&easy) > width(&plain));
+    assert!(width(&hard) < width(&plain));
+    // Hard content keeps its motion; easy content gives up frames instead.
+    assert_eq!(hard.fps, None);
+    assert_eq!(easy.fps, Some(15.0));
+    assert_eq!(plain.fps, Some(15.0));
+    assert_eq!(easy.complexity.unwrap().preset(), Preset::Fast);
+    assert_eq!(hard.complexity.unwrap().preset(), Preset::Slow);
+}
+
+#[test]
+fn estimate_extrapolates_samples_without_encoding() {
+    let scratch = scratch("estimate", 50_000_000);
+    let fake = backend(&[]);
+    let estimate = job(&scratch, options(), &fake).estimate().unwrap();
+
+    assert!(fake
+        .calls()
+        .iter()
+        .all(|c| matches!(c, FakeCall::SampleSize { .. })));
+    assert_eq!(estimate.samples, 3);
+    assert_eq!(estimate.sampled_seconds, 15.0);
+    // Identical samples leave no spread to doubt.
+    assert_eq!(estimate.margin, 0.0);
+    assert!(estimate.output_bytes() < TARGET);
+    assert_eq!(estimate.verdict(), Some(Verdict::Fits));
+    assert!(!scratch.path("out.mp4").exists());
+}
+
+#[test]
+fn estimate_reports_an_unreachable_target() {
+    let scratch = scratch("estimate-over", 50_000_000);
+    let media = probe::parse(MEDIA.as_bytes()).unwrap();
+    let fake = Arc::new(FakeBackend::new(media).sample_bits_per_pixel(0.3));
+    let estimate = job(&scratch, options(), &fake).estimate().unwrap();
+
+    assert!(estimate.output_bytes() > TARGET);
+    assert_eq!(estimate.verdict(), Some(Verdict::Over));
+    assert!(estimate.to_string().contains("not achievable"));
+}
+
+#[test]
+fn estimate_in_quality_mode_has_no_verdict() {
+    let scratch = scratch("estimate-quality", 50_000_000);
+    let fake = backend(&[]);
+    let opts = options().rate_control(RateControl::Quality { crf: None });
+    let estimate = job(&scratch, opts, &fake).estimate().unwrap();
+
+    assert_eq!(estimate.target_bytes, None);
+    assert_eq!(estimate.verdict(), None);
+    assert!(!estimate.to_string().contains("achievable"));
+}